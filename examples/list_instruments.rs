@@ -1,4 +1,3 @@
-use rusb;
 use std::error::Error;
 use std::time::Instant;
 use tmc::list_instruments;
@@ -9,11 +8,17 @@ fn main() -> Result<(), Box<dyn Error>> {
   let timer = Instant::now();
   let instruments = list_instruments(context)?;
 
-  if instruments.len() == 0 {
+  if instruments.is_empty() {
     println!("no instruments found");
   } else {
     for mut instrument in instruments {
       println!("Found instrument: {}", instrument.read_resource_string()?);
+      println!("    Manufacturer: {:?}", instrument.read_manufacturer()?);
+      println!("    Product: {:?}", instrument.read_product()?);
+      println!(
+        "    Interface: {:?}",
+        instrument.read_interface_description()?
+      );
 
       let handle = instrument.open()?;
       println!("    USBTMC: {:?}", handle.usbtmc_capabilities);
@@ -1,4 +1,3 @@
-use rusb;
 use std::error::Error;
 use tmc::list_instruments;
 
@@ -12,7 +11,7 @@ fn main() -> Result<(), Box<dyn Error>> {
   let context = rusb::Context::new()?;
   let instruments = list_instruments(context)?;
 
-  if instruments.len() == 0 {
+  if instruments.is_empty() {
     println!("no instruments found");
     return Ok(());
   }
@@ -37,7 +36,7 @@ fn main() -> Result<(), Box<dyn Error>> {
   }
 
   if let Some(mut handle) = power_sensor {
-    handle.set_max_transfer_size(1024);
+    handle.set_max_transfer_size(1024)?;
     handle.set_term_char(Some(b'\n'))?;
 
     handle.write(&format!("SENS1:FREQ {}\n", FREQ_HZ as u64))?;
@@ -0,0 +1,241 @@
+use crate::class::*;
+use crate::{InstrumentHandle, TMCError, TMCResult, Transport};
+use core::time::Duration;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::thread;
+use tokio::sync::oneshot;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// The sending end of the I/O worker's queue, once it has been started
+static WORKER: Mutex<Option<mpsc::Sender<Job>>> = Mutex::new(None);
+
+/// Queue a job on the I/O worker shared by all async handles, starting the
+/// worker if this is the first
+fn submit(job: Job) -> TMCResult<()> {
+  let mut worker = WORKER.lock().unwrap_or_else(PoisonError::into_inner);
+
+  let jobs = match &*worker {
+    Some(jobs) => jobs.clone(),
+    None => {
+      let (jobs, queue) = mpsc::channel::<Job>();
+      thread::Builder::new()
+        .name("tmc-io".to_owned())
+        .spawn(move || {
+          for job in queue {
+            // one handle's panic mustn't stop the others' requests
+            let _ = panic::catch_unwind(AssertUnwindSafe(job));
+          }
+        })
+        .map_err(|_| TMCError::WorkerStopped)?;
+
+      *worker = Some(jobs.clone());
+      jobs
+    }
+  };
+  drop(worker);
+
+  jobs.send(job).map_err(|_| TMCError::WorkerStopped)
+}
+
+/// An async counterpart to [InstrumentHandle], for use with tokio.
+///
+/// The blocking I/O is done on a single worker thread shared by every async
+/// handle, which runs requests one at a time in the order they were made.
+/// A request therefore waits for those already queued for other
+/// instruments, each bounded by its handle's timeout.  Dropping a future
+/// returned by this handle cancels the request if it hasn't started yet;
+/// if it has, the worker finishes it and throws the result away, so the
+/// instrument is never left part-way through a message.
+///
+/// The underlying handle is dropped once the `AsyncInstrumentHandle` has
+/// been dropped and any requests it queued are done.
+pub struct AsyncInstrumentHandle<T: Transport> {
+  handle: Arc<Mutex<InstrumentHandle<T>>>,
+  timeout: Duration,
+  endpoints: TMCInterface,
+
+  pub usbtmc_capabilities: USBTMCCapabilities,
+  pub usb488_capabilities: Option<USB488Capabilities>,
+  pub scpi_id: Option<String>,
+}
+
+impl<T: Transport + Send + 'static> AsyncInstrumentHandle<T> {
+  /// Hand a connected handle over to the shared I/O worker
+  pub fn new(handle: InstrumentHandle<T>) -> TMCResult<Self> {
+    Ok(Self {
+      timeout: handle.get_timeout(),
+      endpoints: handle.endpoints().clone(),
+      usbtmc_capabilities: handle.usbtmc_capabilities.clone(),
+      usb488_capabilities: handle.usb488_capabilities.clone(),
+      scpi_id: handle.scpi_id.clone(),
+
+      handle: Arc::new(Mutex::new(handle)),
+    })
+  }
+
+  /// Queue a job for the underlying handle on the I/O worker
+  fn submit<F>(&self, f: F) -> TMCResult<()>
+  where
+    F: FnOnce(&mut InstrumentHandle<T>) + Send + 'static,
+  {
+    let handle = self.handle.clone();
+    submit(Box::new(move || {
+      let mut handle = handle.lock().unwrap_or_else(PoisonError::into_inner);
+      f(&mut handle)
+    }))
+  }
+
+  /// Run a function with exclusive access to the underlying blocking handle,
+  /// on the I/O worker.  This gives access to everything that doesn't have an
+  /// async wrapper of its own.
+  pub async fn with_handle<R, F>(&self, f: F) -> TMCResult<R>
+  where
+    R: Send + 'static,
+    F: FnOnce(&mut InstrumentHandle<T>) -> TMCResult<R> + Send + 'static,
+  {
+    let (result_tx, result_rx) = oneshot::channel();
+
+    self.submit(move |handle| {
+      // the caller has already given up; don't touch the instrument
+      if result_tx.is_closed() {
+        return;
+      }
+
+      let _ = result_tx.send(f(handle));
+    })?;
+    result_rx.await.map_err(|_| TMCError::WorkerStopped)?
+  }
+
+  /// The TMC interface the underlying handle communicates with
+  pub fn endpoints(&self) -> &TMCInterface {
+    &self.endpoints
+  }
+
+  pub fn get_timeout(&self) -> Duration {
+    self.timeout
+  }
+
+  /// Set the timeout for each transfer.  Takes effect for all requests made
+  /// after this call.
+  pub fn set_timeout(&mut self, timeout: Duration) {
+    self.timeout = timeout;
+
+    let _ = self.submit(move |handle| handle.set_timeout(timeout));
+  }
+
+  pub async fn set_term_char(&self, term_char: Option<u8>) -> TMCResult<()> {
+    self
+      .with_handle(move |handle| handle.set_term_char(term_char))
+      .await
+  }
+
+  pub async fn set_max_transfer_size(&self, max_transfer_size: u32) -> TMCResult<()> {
+    self
+      .with_handle(move |handle| handle.set_max_transfer_size(max_transfer_size))
+      .await
+  }
+
+  /// Send USBTMC "clear" command
+  pub async fn clear(&self) -> TMCResult<()> {
+    self.with_handle(|handle| handle.clear()).await
+  }
+
+  pub async fn pulse(&self) -> TMCResult<()> {
+    self.with_handle(|handle| handle.pulse()).await
+  }
+
+  /// Write a command message to the instrument
+  pub async fn write_raw(&self, data: &[u8]) -> TMCResult<()> {
+    let data = data.to_vec();
+    self
+      .with_handle(move |handle| handle.write_raw(&data))
+      .await
+  }
+
+  /// Read response data from the instrument
+  pub async fn read_raw(&self, transfer_size: Option<u32>) -> TMCResult<Vec<u8>> {
+    self
+      .with_handle(move |handle| handle.read_raw(transfer_size))
+      .await
+  }
+
+  /// Read UTF-8 response data from the instrument
+  pub async fn read(&self, transfer_size: Option<u32>) -> TMCResult<String> {
+    self
+      .with_handle(move |handle| handle.read(transfer_size))
+      .await
+  }
+
+  /// Write a UTF-8 command message to the instrument
+  pub async fn write(&self, message: &str) -> TMCResult<()> {
+    self.write_raw(message.as_bytes()).await
+  }
+
+  /// Write a UTF-8 command message to the instrument and read a UTF-8 response
+  pub async fn ask(&self, data: &str) -> TMCResult<String> {
+    let response_data = self.ask_raw(data.as_bytes()).await?;
+    let response_str = String::from_utf8(response_data)?;
+    Ok(response_str)
+  }
+
+  /// Write a command message to the instrument and read a response
+  pub async fn ask_raw(&self, data: &[u8]) -> TMCResult<Vec<u8>> {
+    let data = data.to_vec();
+    self.with_handle(move |handle| handle.ask_raw(&data)).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::transport::tests::FakeTransport;
+  use std::future::Future;
+  use std::pin::Pin;
+  use std::task::{Context, Poll, Wake, Waker};
+  use std::thread::Thread;
+
+  struct ThreadWaker(Thread);
+
+  impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+      self.0.unpark();
+    }
+  }
+
+  /// Run a future to completion on this thread
+  fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut context = Context::from_waker(&waker);
+
+    loop {
+      match Pin::as_mut(&mut future).poll(&mut context) {
+        Poll::Ready(output) => return output,
+        Poll::Pending => thread::park(),
+      }
+    }
+  }
+
+  fn connect() -> AsyncInstrumentHandle<FakeTransport> {
+    let handle =
+      InstrumentHandle::connect(FakeTransport::default(), TMCInterface::for_tests()).unwrap();
+    AsyncInstrumentHandle::new(handle).unwrap()
+  }
+
+  #[test]
+  fn handles_share_one_worker() {
+    let first = connect();
+    let second = connect();
+
+    let worker_thread = |handle: &AsyncInstrumentHandle<FakeTransport>| {
+      block_on(handle.with_handle(|_| Ok(thread::current().id()))).unwrap()
+    };
+    assert_eq!(worker_thread(&first), worker_thread(&second));
+    assert_ne!(worker_thread(&first), thread::current().id());
+
+    assert_eq!(block_on(first.ask("MEAS?")).unwrap(), "42\n");
+    assert_eq!(first.scpi_id.as_deref(), Some("FAKE,MODEL,1234,1.0"));
+  }
+}
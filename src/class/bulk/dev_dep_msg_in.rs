@@ -73,9 +73,25 @@ impl DevDepMsgInHeader {
     })
   }
 
+  /// Decode a DEV_DEP_MSG_IN transfer, strictly checking the header
   pub fn decode_transfer(buf: &[u8]) -> Result<(Self, &[u8]), ClassError> {
+    Self::decode_transfer_with(buf, &Tolerance::default())
+  }
+
+  /// Decode a DEV_DEP_MSG_IN transfer, accepting the given deviations from the spec
+  pub fn decode_transfer_with<'a>(
+    buf: &'a [u8],
+    tolerance: &Tolerance,
+  ) -> Result<(Self, &'a [u8]), ClassError> {
     let header = Self::unpack(buf)?;
-    let data = &buf[HEADER_SIZE..HEADER_SIZE + header.transfer_size as usize];
+
+    if header.bulk_in_header.msg_id != MsgIdIn::DevDepMsgIn {
+      return Err(ClassError::UnexpectedMsgId);
+    }
+    tolerance.check_reserved(&[header.bulk_in_header.reserved])?;
+    tolerance.check_reserved(&buf[9..HEADER_SIZE])?;
+
+    let data = tolerance.transfer_data(buf, header.transfer_size)?;
     Ok((header, data))
   }
 
@@ -86,4 +102,109 @@ impl DevDepMsgInHeader {
   pub fn has_term_char(&self) -> bool {
     self.transfer_attributes & 0x02 != 0
   }
+
+  /// Encode a DEV_DEP_MSG_IN transfer as a device would send it, for tests
+  #[cfg(test)]
+  pub(crate) fn encode_message(b_tag: u8, data: &[u8], eom: bool, buf: &mut Vec<u8>) {
+    buf.resize(HEADER_SIZE, 0u8);
+    BulkInHeader::new(MsgIdIn::DevDepMsgIn, b_tag).pack(buf);
+    LittleEndian::write_u32(&mut buf[4..8], data.len() as u32);
+    buf[8] = if eom { 1 } else { 0 };
+
+    buf.extend_from_slice(data);
+
+    // pad to next multiple of 4
+    buf.resize((buf.len() + 3) & !3, 0);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn transfer(b_tag: u8, data: &[u8], eom: bool) -> Vec<u8> {
+    let mut buf = Vec::new();
+    DevDepMsgInHeader::encode_message(b_tag, data, eom, &mut buf);
+    buf
+  }
+
+  #[test]
+  fn decodes_transfer() {
+    let buf = transfer(7, b"hello", true);
+    let (header, data) = DevDepMsgInHeader::decode_transfer(&buf).unwrap();
+
+    assert_eq!(header.bulk_in_header.b_tag, 7);
+    assert_eq!(header.transfer_size, 5);
+    assert!(header.is_eom());
+    assert!(!header.has_term_char());
+    assert_eq!(data, b"hello");
+  }
+
+  #[test]
+  fn truncated_buffer() {
+    let buf = transfer(1, b"", true);
+    assert_eq!(
+      DevDepMsgInHeader::decode_transfer(&buf[..HEADER_SIZE - 1]),
+      Err(ClassError::TruncatedHeader)
+    );
+  }
+
+  #[test]
+  fn oversize_transfer() {
+    // announces more than follows it
+    let mut buf = transfer(1, b"hel", true);
+    buf.truncate(HEADER_SIZE + 3);
+    LittleEndian::write_u32(&mut buf[4..8], 10);
+    assert_eq!(
+      DevDepMsgInHeader::decode_transfer(&buf),
+      Err(ClassError::OversizeTransfer)
+    );
+
+    let tolerance = Tolerance {
+      oversize_transfer: true,
+      ..Tolerance::default()
+    };
+    let (header, data) = DevDepMsgInHeader::decode_transfer_with(&buf, &tolerance).unwrap();
+    assert_eq!(header.transfer_size, 10);
+    assert_eq!(data, b"hel");
+  }
+
+  #[test]
+  fn wrong_msg_id() {
+    let mut buf = transfer(1, b"", true);
+
+    buf[0] = MsgIdIn::VendorSpecificIn.into();
+    assert_eq!(
+      DevDepMsgInHeader::decode_transfer(&buf),
+      Err(ClassError::UnexpectedMsgId)
+    );
+
+    buf[0] = 0x55;
+    assert_eq!(
+      DevDepMsgInHeader::decode_transfer(&buf),
+      Err(ClassError::InvalidMsgId)
+    );
+  }
+
+  #[test]
+  fn non_zero_reserved() {
+    let tolerance = Tolerance {
+      non_zero_reserved: true,
+      ..Tolerance::default()
+    };
+
+    for &offset in &[3, 9, 10, 11] {
+      let mut buf = transfer(1, b"ok", true);
+      buf[offset] = 0xAA;
+
+      assert_eq!(
+        DevDepMsgInHeader::decode_transfer(&buf),
+        Err(ClassError::NonZeroReserved),
+        "reserved byte {}",
+        offset
+      );
+      let (_, data) = DevDepMsgInHeader::decode_transfer_with(&buf, &tolerance).unwrap();
+      assert_eq!(data, b"ok");
+    }
+  }
 }
@@ -11,6 +11,9 @@ pub enum MsgIdOut {
   RequestDevDepMsgIn = 2,
   VendorSpecificOut = 3,
   RequestVendorSpecificIn = 4,
+
+  // USB488 subclass messages
+  Trigger = 128,
 }
 
 impl From<MsgIdOut> for u8 {
@@ -27,6 +30,7 @@ impl TryFrom<u8> for MsgIdOut {
       2 => Ok(Self::RequestDevDepMsgIn),
       3 => Ok(Self::VendorSpecificOut),
       4 => Ok(Self::RequestVendorSpecificIn),
+      128 => Ok(Self::Trigger),
       _ => Err(ClassError::InvalidMsgId),
     }
   }
@@ -0,0 +1,60 @@
+use crate::class::*;
+
+/// Deviations from the spec to accept when decoding bulk-in transfers.
+///
+/// By default nothing is tolerated, and any of these problems is reported
+/// as an error.  Some devices are known to get these details wrong while
+/// otherwise working fine.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Tolerance {
+  /// Accept a header announcing more data than actually followed it, or more
+  /// than was asked for, and use whatever data did arrive.  A transfer is
+  /// then considered finished as soon as the device sends a short packet, so
+  /// responses split into several short-packet-terminated pieces can't be
+  /// reassembled.
+  pub oversize_transfer: bool,
+
+  /// Accept a response whose bTag doesn't match the request it answers.
+  pub mismatched_tag: bool,
+
+  /// Accept headers with non-zero reserved bytes.
+  pub non_zero_reserved: bool,
+}
+
+impl Tolerance {
+  /// Check that reserved bytes in a header are zero.
+  pub fn check_reserved(&self, reserved: &[u8]) -> Result<(), ClassError> {
+    if !self.non_zero_reserved && reserved.iter().any(|&byte| byte != 0) {
+      Err(ClassError::NonZeroReserved)
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Check that a response's bTag matches the request's.
+  pub fn check_tag(&self, expected: u8, actual: u8) -> Result<(), ClassError> {
+    if !self.mismatched_tag && expected != actual {
+      Err(ClassError::MismatchedTag)
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Get the data announced by a header, checking that it all arrived.
+  pub fn transfer_data<'a>(
+    &self,
+    buf: &'a [u8],
+    transfer_size: u32,
+  ) -> Result<&'a [u8], ClassError> {
+    let available = buf.len().saturating_sub(HEADER_SIZE);
+    let transfer_size = transfer_size as usize;
+
+    if transfer_size <= available {
+      Ok(&buf[HEADER_SIZE..HEADER_SIZE + transfer_size])
+    } else if self.oversize_transfer {
+      Ok(&buf[HEADER_SIZE..HEADER_SIZE + available])
+    } else {
+      Err(ClassError::OversizeTransfer)
+    }
+  }
+}
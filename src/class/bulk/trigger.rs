@@ -0,0 +1,28 @@
+use crate::class::*;
+
+/// Header for the USB488 TRIGGER message (USB488 section 3.2.1.1).  The
+/// message consists of just this header, with no data following it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TriggerHeader {
+  pub bulk_out_header: BulkOutHeader,
+}
+
+impl TriggerHeader {
+  pub fn new(b_tag: u8) -> Self {
+    let bulk_out_header = BulkOutHeader::new(MsgIdOut::Trigger, b_tag);
+
+    Self { bulk_out_header }
+  }
+
+  pub fn pack(&self, buf: &mut [u8]) {
+    self.bulk_out_header.pack(buf);
+    for byte in buf[4..HEADER_SIZE].iter_mut() {
+      *byte = 0;
+    }
+  }
+
+  pub fn encode_message(b_tag: u8, buf: &mut Vec<u8>) {
+    buf.resize(HEADER_SIZE, 0);
+    TriggerHeader::new(b_tag).pack(buf);
+  }
+}
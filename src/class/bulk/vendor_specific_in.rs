@@ -20,6 +20,14 @@ impl RequestVendorSpecificInHeader {
   pub fn pack(&self, buf: &mut [u8]) {
     self.bulk_out_header.pack(buf);
     LittleEndian::write_u32(&mut buf[4..8], self.transfer_size);
+    for byte in buf[8..HEADER_SIZE].iter_mut() {
+      *byte = 0;
+    }
+  }
+
+  pub fn encode_message(b_tag: u8, transfer_size: u32, buf: &mut Vec<u8>) {
+    buf.resize(HEADER_SIZE, 0);
+    RequestVendorSpecificInHeader::new(b_tag, transfer_size).pack(buf);
   }
 }
 
@@ -41,9 +49,25 @@ impl VendorSpecificInHeader {
     })
   }
 
+  /// Decode a VENDOR_SPECIFIC_IN transfer, strictly checking the header
   pub fn decode_transfer(buf: &[u8]) -> Result<(Self, &[u8]), ClassError> {
+    Self::decode_transfer_with(buf, &Tolerance::default())
+  }
+
+  /// Decode a VENDOR_SPECIFIC_IN transfer, accepting the given deviations from the spec
+  pub fn decode_transfer_with<'a>(
+    buf: &'a [u8],
+    tolerance: &Tolerance,
+  ) -> Result<(Self, &'a [u8]), ClassError> {
     let header = Self::unpack(buf)?;
-    let data = &buf[HEADER_SIZE..HEADER_SIZE + header.transfer_size as usize];
+
+    if header.bulk_in_header.msg_id != MsgIdIn::VendorSpecificIn {
+      return Err(ClassError::UnexpectedMsgId);
+    }
+    tolerance.check_reserved(&[header.bulk_in_header.reserved])?;
+    tolerance.check_reserved(&buf[8..HEADER_SIZE])?;
+
+    let data = tolerance.transfer_data(buf, header.transfer_size)?;
     Ok((header, data))
   }
 }
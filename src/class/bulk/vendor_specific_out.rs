@@ -20,5 +20,24 @@ impl VendorSpecificOutHeader {
   pub fn pack(&self, buf: &mut [u8]) {
     self.bulk_out_header.pack(buf);
     LittleEndian::write_u32(&mut buf[4..8], self.transfer_size);
+    for byte in buf[8..HEADER_SIZE].iter_mut() {
+      *byte = 0;
+    }
+  }
+
+  pub fn encode_message(b_tag: u8, data: &[u8], buf: &mut Vec<u8>) {
+    // add the header
+    buf.resize(HEADER_SIZE, 0u8);
+    VendorSpecificOutHeader::new(b_tag, data.len() as u32).pack(buf);
+
+    // add the data
+    buf.extend_from_slice(data);
+
+    // pad to next multiple of 4
+    let len = buf.len();
+    let padded_len = (len + 3) & !3;
+    if len != padded_len {
+      buf.resize(padded_len, 0);
+    }
   }
 }
@@ -0,0 +1,75 @@
+use crate::class::*;
+use byteorder::{ByteOrder, LittleEndian};
+use std::convert::TryFrom;
+
+/// Response to an INITIATE_ABORT_BULK_OUT or INITIATE_ABORT_BULK_IN request
+/// (Sections 4.2.1.2 and 4.2.1.4).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InitiateAbortResponse {
+  pub status: Status,
+  pub b_tag: u8,
+}
+
+impl InitiateAbortResponse {
+  pub fn parse(buf: &[u8]) -> Result<Self, ClassError> {
+    if buf.len() < 2 {
+      Err(ClassError::TruncatedControlResponse)
+    } else {
+      Ok(Self {
+        status: Status::try_from(buf[0])?,
+        b_tag: buf[1],
+      })
+    }
+  }
+}
+
+/// Response to a CHECK_ABORT_BULK_OUT_STATUS request (Section 4.2.1.3).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CheckAbortBulkOutStatusResponse {
+  pub status: Status,
+
+  /// The number of bytes received by the device in the aborted transfer,
+  /// excluding headers and alignment bytes.
+  pub n_bytes_rxd: u32,
+}
+
+impl CheckAbortBulkOutStatusResponse {
+  pub fn parse(buf: &[u8]) -> Result<Self, ClassError> {
+    if buf.len() < 8 {
+      Err(ClassError::TruncatedControlResponse)
+    } else {
+      Ok(Self {
+        status: Status::try_from(buf[0])?,
+        n_bytes_rxd: LittleEndian::read_u32(&buf[4..8]),
+      })
+    }
+  }
+}
+
+/// Response to a CHECK_ABORT_BULK_IN_STATUS request (Section 4.2.1.5).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CheckAbortBulkInStatusResponse {
+  pub status: Status,
+
+  /// bmAbortBulkIn D0: the device still has data queued in its bulk-in FIFO,
+  /// which the host must read before the abort can complete.
+  pub more_data: bool,
+
+  /// The number of bytes transmitted by the device in the aborted transfer,
+  /// excluding headers and alignment bytes.
+  pub n_bytes_txd: u32,
+}
+
+impl CheckAbortBulkInStatusResponse {
+  pub fn parse(buf: &[u8]) -> Result<Self, ClassError> {
+    if buf.len() < 8 {
+      Err(ClassError::TruncatedControlResponse)
+    } else {
+      Ok(Self {
+        status: Status::try_from(buf[0])?,
+        more_data: buf[1] & 0x01 != 0,
+        n_bytes_txd: LittleEndian::read_u32(&buf[4..8]),
+      })
+    }
+  }
+}
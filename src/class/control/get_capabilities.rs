@@ -11,6 +11,12 @@ pub struct USBTMCCapabilities {
   pub term_char: bool,
 }
 
+impl Default for USBTMCCapabilities {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl USBTMCCapabilities {
   /// create a new placeholder value, which is not valid.  Mostly just to avoid needing Option
   /// in the instrument handle type, since these will be read immediately during the connection
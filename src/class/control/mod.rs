@@ -1,7 +1,11 @@
+mod abort;
 mod get_capabilities;
 mod request;
 mod status;
+mod usb488;
 
+pub use abort::*;
 pub use get_capabilities::*;
 pub use request::*;
 pub use status::*;
+pub use usb488::*;
@@ -12,6 +12,12 @@ pub enum ControlRequest {
   CheckClearStatus = 6,
   GetCapabilities = 7,
   IndicatorPulse = 64,
+
+  // USB488 subclass requests
+  ReadStatusByte = 128,
+  RenControl = 160,
+  GoToLocal = 161,
+  LocalLockout = 162,
 }
 
 impl From<ControlRequest> for u8 {
@@ -23,7 +29,7 @@ impl From<ControlRequest> for u8 {
 impl ControlRequest {
   /// Attempt to read the first byte of the provided buffer as a USB TMC status code
   pub fn read_response_status(buf: &[u8]) -> Result<Status, ClassError> {
-    if buf.len() < 1 {
+    if buf.is_empty() {
       Err(ClassError::TruncatedControlResponse)
     } else {
       Status::try_from(buf[0])
@@ -32,7 +38,7 @@ impl ControlRequest {
 
   /// Check the first byte of a buffer (if it's long enough) and ensure it indicates a "success" status.
   pub fn check_response_status(buf: &[u8]) -> Result<(), ClassError> {
-    if buf.len() < 1 {
+    if buf.is_empty() {
       Err(ClassError::TruncatedControlResponse)
     } else {
       Status::try_from(buf[0])?.check()
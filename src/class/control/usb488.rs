@@ -0,0 +1,28 @@
+use crate::class::*;
+use std::convert::TryFrom;
+
+/// Response to a USB488 READ_STATUS_BYTE request (USB488 section 4.3.1).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ReadStatusByteResponse {
+  pub status: Status,
+  pub b_tag: u8,
+
+  /// The status byte, if the device has no interrupt-in endpoint.  Otherwise
+  /// this field is reserved and the status byte is delivered in an
+  /// interrupt-in notification instead.
+  pub status_byte: u8,
+}
+
+impl ReadStatusByteResponse {
+  pub fn parse(buf: &[u8]) -> Result<Self, ClassError> {
+    if buf.len() < 3 {
+      Err(ClassError::TruncatedControlResponse)
+    } else {
+      Ok(Self {
+        status: Status::try_from(buf[0])?,
+        b_tag: buf[1],
+        status_byte: buf[2],
+      })
+    }
+  }
+}
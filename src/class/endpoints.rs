@@ -1,11 +1,15 @@
 /// Information about a USB device's TMC interface, needed to find the right
 /// endpoints and such for communication to the instrument.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct TMCInterface {
   /// The ID of a USB interface on the instrument that complies to the
   /// USB Test and Measurement Class
   pub interface_number: u8,
 
+  /// The alternate setting of that interface in which it complies to the
+  /// USB Test and Measurement Class
+  pub alternate_setting: u8,
+
   /// The interface's "protocol code", used to identify sub-classes
   /// such as USB488
   pub interface_protocol: u8,
@@ -30,3 +34,21 @@ pub struct TMCInterface {
   pub bulk_out_max_packet_size: u16,
   pub bulk_in_max_packet_size: u16,
 }
+
+#[cfg(test)]
+impl TMCInterface {
+  /// A USB488 interface with full-speed packet sizes, for tests
+  pub(crate) fn for_tests() -> Self {
+    Self {
+      interface_number: 0,
+      alternate_setting: 0,
+      interface_protocol: 1,
+      bulk_out_address: 0x01,
+      bulk_in_address: 0x82,
+      interrupt_in_address: None,
+      control_in_max_packet_size: 64,
+      bulk_out_max_packet_size: 64,
+      bulk_in_max_packet_size: 64,
+    }
+  }
+}
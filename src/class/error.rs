@@ -8,10 +8,15 @@ pub enum ClassError {
   InvalidCapabilities,
   InvalidMsgId,
   InvalidTermChar,
+  InvalidTransferSize,
+  MismatchedTag,
+  NonZeroReserved,
+  OversizeTransfer,
   TagCheckFailure,
   TruncatedBulkOut,
   TruncatedControlResponse,
   TruncatedHeader,
+  UnexpectedMsgId,
   UnexpectedStatus(Status),
   UnsupportedFeature,
 }
@@ -0,0 +1,128 @@
+use crate::class::*;
+
+/// A notification received on the interrupt-in endpoint (USBTMC section 3.4,
+/// USB488 section 3.4).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Notification {
+  /// A USB488 device is requesting service (bNotify1 = 0x81)
+  ServiceRequest { status_byte: u8 },
+
+  /// A USB488 device is answering a READ_STATUS_BYTE request with the given bTag
+  StatusByte { b_tag: u8, status_byte: u8 },
+
+  /// A vendor-specific notification (bNotify1 D7 set, outside of the values
+  /// defined by USB488), with the raw bNotify1 value and the bytes following it
+  VendorSpecific { b_notify1: u8, data: Vec<u8> },
+
+  /// A notification with bNotify1 D7 clear, which is reserved by the spec
+  Reserved { b_notify1: u8, data: Vec<u8> },
+}
+
+impl Notification {
+  /// Decode an interrupt-in packet.  `usb488` selects whether the USB488
+  /// subclass interpretation of bNotify1 applies.
+  pub fn parse(buf: &[u8], usb488: bool) -> Result<Self, ClassError> {
+    if buf.len() < 2 {
+      return Err(ClassError::TruncatedHeader);
+    }
+
+    let b_notify1 = buf[0];
+    let b_tag = b_notify1 & 0x7F;
+
+    Ok(match (b_notify1 & 0x80 != 0, b_tag) {
+      (true, 1) if usb488 => Self::ServiceRequest {
+        status_byte: buf[1],
+      },
+      (true, 2..=127) if usb488 => Self::StatusByte {
+        b_tag,
+        status_byte: buf[1],
+      },
+      (true, _) => Self::VendorSpecific {
+        b_notify1,
+        data: buf[1..].to_vec(),
+      },
+      (false, _) => Self::Reserved {
+        b_notify1,
+        data: buf[1..].to_vec(),
+      },
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn service_request() {
+    assert_eq!(
+      Notification::parse(&[0x81, 0x40], true),
+      Ok(Notification::ServiceRequest { status_byte: 0x40 })
+    );
+  }
+
+  #[test]
+  fn status_byte() {
+    for &b_tag in &[2u8, 0x55, 127] {
+      assert_eq!(
+        Notification::parse(&[0x80 | b_tag, 0x10], true),
+        Ok(Notification::StatusByte {
+          b_tag,
+          status_byte: 0x10
+        })
+      );
+    }
+  }
+
+  #[test]
+  fn vendor_specific() {
+    // bTag 0 isn't used by READ_STATUS_BYTE, so 0x80 is left to the vendor
+    assert_eq!(
+      Notification::parse(&[0x80, 0x01, 0x02], true),
+      Ok(Notification::VendorSpecific {
+        b_notify1: 0x80,
+        data: vec![0x01, 0x02]
+      })
+    );
+  }
+
+  #[test]
+  fn vendor_specific_without_usb488() {
+    for &b_notify1 in &[0x80u8, 0x81, 0x85, 0xFF] {
+      assert_eq!(
+        Notification::parse(&[b_notify1, 0x40], false),
+        Ok(Notification::VendorSpecific {
+          b_notify1,
+          data: vec![0x40]
+        })
+      );
+    }
+  }
+
+  #[test]
+  fn reserved() {
+    for &usb488 in &[true, false] {
+      for &b_notify1 in &[0x00u8, 0x01, 0x7F] {
+        assert_eq!(
+          Notification::parse(&[b_notify1, 0x40], usb488),
+          Ok(Notification::Reserved {
+            b_notify1,
+            data: vec![0x40]
+          })
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn truncated_packet() {
+    assert_eq!(
+      Notification::parse(&[], true),
+      Err(ClassError::TruncatedHeader)
+    );
+    assert_eq!(
+      Notification::parse(&[0x81], true),
+      Err(ClassError::TruncatedHeader)
+    );
+  }
+}
@@ -4,13 +4,20 @@
 //!    Universal Serial Bus Test and Measurement Class Specification (USBTMC)
 //!    Revision 1.0 April 14, 2003
 //!
+//! and its USB488 subclass specification:
+//!
+//!    Universal Serial Bus Test and Measurement Class, Subclass USB488
+//!    Specification (USBTMC-USB488) Revision 1.0 April 14, 2003
+//!
 
 mod bulk;
 mod control;
 mod endpoints;
 mod error;
+mod interrupt;
 
 pub use bulk::*;
 pub use control::*;
 pub use endpoints::*;
 pub use error::*;
+pub use interrupt::*;
@@ -1,6 +1,7 @@
 pub use crate::class::ClassError;
 use std::error::Error;
 use std::fmt;
+use std::io;
 use std::string::FromUtf8Error;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -8,11 +9,27 @@ pub enum TMCError {
   /// An error occurred in a generic USB operation
   Rusb(rusb::Error),
 
+  /// A bulk transfer failed with the given USB error, and the connection was
+  /// recovered according to the handle's [RecoveryPolicy](crate::RecoveryPolicy).
+  /// The message in progress was lost, but the instrument is ready for the next one.
+  Recovered(rusb::Error),
+
   /// An error occurred in the handling of a USB TMC class operation
   Class(ClassError),
 
   /// The application requested a string response, but the data from the device was not valid UTF-8
   FromUtf8Error(FromUtf8Error),
+
+  /// The handle's background I/O worker has stopped, so no more requests
+  /// can be made through it
+  WorkerStopped,
+
+  /// The instrument went away and a new connection to it has been made.  The
+  /// request in progress was lost, and the instrument may have been reset.
+  Reconnected,
+
+  /// The given text isn't a valid VISA resource string for a USB instrument
+  InvalidResourceString(String),
 }
 
 pub type TMCResult<T> = Result<T, TMCError>;
@@ -35,6 +52,15 @@ impl From<FromUtf8Error> for TMCError {
   }
 }
 
+impl From<TMCError> for io::Error {
+  fn from(item: TMCError) -> Self {
+    match item {
+      TMCError::Rusb(rusb::Error::Timeout) => io::Error::new(io::ErrorKind::TimedOut, item),
+      _ => io::Error::other(item),
+    }
+  }
+}
+
 impl fmt::Display for TMCError {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     use TMCError::*;
@@ -43,12 +69,27 @@ impl fmt::Display for TMCError {
       Rusb(msg) => {
         write!(f, "USB Error: {}", msg)
       }
+      Recovered(msg) => {
+        write!(f, "USB Error: {} (transfer aborted and recovered)", msg)
+      }
       Class(msg) => {
         write!(f, "USB TMC Error: {}", msg)
       }
       FromUtf8Error(msg) => {
         write!(f, "Error decoding UTF-8 data: {}", msg)
       }
+      WorkerStopped => {
+        write!(f, "The I/O worker for this handle has stopped")
+      }
+      Reconnected => {
+        write!(
+          f,
+          "The instrument was disconnected and has been reconnected"
+        )
+      }
+      InvalidResourceString(resource) => {
+        write!(f, "Invalid USB resource string: {:?}", resource)
+      }
     }
   }
 }
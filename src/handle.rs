@@ -1,101 +1,140 @@
 use crate::class::*;
-use crate::{Instrument, TMCResult};
+use crate::protocol::*;
+use crate::{
+  Instrument, MessageReader, MessageWriter, OpenOptions, Quirks, TMCError, TMCResult, Transport,
+  UsbTransport,
+};
 use core::time::Duration;
-use rusb::DeviceHandle;
 use rusb::UsbContext;
+use std::collections::VecDeque;
+use std::io;
 use std::str;
 use std::thread::sleep;
+use std::time::Instant;
+
+/// What an [InstrumentHandle] should do when a bulk transfer times out or
+/// stalls part-way through a message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RecoveryPolicy {
+  /// Pass the USB error straight up and leave recovery to the application.
+  Manual,
+
+  /// Clear the endpoint halt and run the USBTMC abort sequence for the
+  /// direction that failed, so the next transfer starts from a clean state.
+  /// The failed operation returns [TMCError::Recovered].
+  Abort,
+}
+
+/// A function to be called whenever an [InstrumentHandle] receives a
+/// notification on the interrupt-in endpoint.
+pub type NotificationCallback = Box<dyn FnMut(&Notification) + Send>;
+
+/// Notifications received while waiting for something else are kept for
+/// `wait_for_notification`, up to this many (oldest are discarded first).
+const MAX_QUEUED_NOTIFICATIONS: usize = 64;
+
+/// How long to wait for stale bulk-in data when connecting
+const FLUSH_TIMEOUT: Duration = Duration::from_millis(50);
 
-pub struct InstrumentHandle<Ctx: UsbContext> {
-  usb: DeviceHandle<Ctx>,
+/// How many replies to throw away while looking for the answer to `*IDN?`
+const MAX_STALE_REPLIES: usize = 3;
 
-  b_tag: u8,
+pub struct InstrumentHandle<T: Transport> {
+  transport: T,
+  engine: Engine,
+
+  status_b_tag: u8,
   max_transfer_size: u32,
   term_char: Option<u8>,
   timeout: Duration,
+  recovery_policy: RecoveryPolicy,
+  local_on_drop: bool,
+  notifications: VecDeque<Notification>,
+  notification_callback: Option<NotificationCallback>,
+  quirks: Quirks,
+  last_write: Option<Instant>,
 
-  pub instrument: Instrument<Ctx>,
   pub usbtmc_capabilities: USBTMCCapabilities,
   pub usb488_capabilities: Option<USB488Capabilities>,
   pub scpi_id: Option<String>,
-
-  // When connecting, we may need to reconfigure some stuff.  Remember the
-  // previous state here and restore it on drop().
-  restore_config: Option<u8>,
-  reattach_kernel_driver: Vec<u8>,
 }
 
-impl<Ctx: UsbContext> Drop for InstrumentHandle<Ctx> {
+impl<T: Transport> Drop for InstrumentHandle<T> {
   fn drop(&mut self) {
-    // TODO: is there something more useful we can do if these fail?
-    let endpoints = &self.instrument.endpoints;
-
-    let _ = self.usb.release_interface(endpoints.interface_number);
-
-    if let Some(old_config) = self.restore_config {
-      let _ = self.usb.set_active_configuration(old_config);
+    // TODO: is there something more useful we can do if this fails?
+    if self.local_on_drop {
+      let _ = self.ren_control(false);
     }
+  }
+}
 
-    for &interface in self.reattach_kernel_driver.iter() {
-      let _ = self.usb.attach_kernel_driver(interface);
-    }
+impl<Ctx: UsbContext> InstrumentHandle<UsbTransport<Ctx>> {
+  /// The instrument this handle is connected to
+  pub fn instrument(&self) -> &Instrument<Ctx> {
+    &self.transport.instrument
   }
 }
 
-impl<Ctx: UsbContext> InstrumentHandle<Ctx> {
-  pub(crate) fn connect(instrument: Instrument<Ctx>) -> TMCResult<Self> {
-    let usb = instrument.device.open()?;
+impl<T: Transport> InstrumentHandle<T> {
+  /// Start a USBTMC session with an instrument, over a transport which has
+  /// already claimed the TMC interface described by `endpoints`.
+  ///
+  /// Most applications should use [Instrument::open] instead.
+  pub fn connect(transport: T, endpoints: TMCInterface) -> TMCResult<Self> {
+    Self::connect_with(transport, endpoints, &OpenOptions::new())
+  }
+
+  /// Start a USBTMC session like [InstrumentHandle::connect], with settings
+  /// from `options`.  Those concerning how the interface is claimed are up to
+  /// the transport, and are ignored here.  Unless `options` sets the quirks
+  /// to work around, they're looked up by the IDs the transport reports.
+  pub fn connect_with(
+    transport: T,
+    endpoints: TMCInterface,
+    options: &OpenOptions,
+  ) -> TMCResult<Self> {
+    let quirks = options
+      .get_quirks()
+      .or_else(|| {
+        let (vendor_id, product_id) = transport.device_ids()?;
+        Some(Quirks::for_device(vendor_id, product_id))
+      })
+      .unwrap_or_default();
 
     let mut handle = Self {
-      instrument,
-      usb,
+      transport,
+      engine: Engine::new(endpoints),
 
-      b_tag: 0,
-      max_transfer_size: 1024 * 1024,
-      timeout: Duration::from_secs(1),
+      status_b_tag: 1,
+      max_transfer_size: options.get_max_transfer_size(),
+      timeout: options.get_timeout(),
       term_char: None,
-
-      restore_config: None,
-      reattach_kernel_driver: Vec::new(),
+      recovery_policy: RecoveryPolicy::Manual,
+      local_on_drop: false,
+      notifications: VecDeque::new(),
+      notification_callback: None,
+      quirks,
+      last_write: None,
 
       usbtmc_capabilities: USBTMCCapabilities::new(),
       usb488_capabilities: None,
       scpi_id: None,
     };
-    let usb = &mut handle.usb;
-    let endpoints = &handle.instrument.endpoints;
-
-    let old_config = usb.active_configuration()?;
 
-    if old_config != 0 {
-      match handle.instrument.device.config_descriptor(old_config) {
-        Err(rusb::Error::NotFound) => {}
-        Err(rusb_error) => return Err(rusb_error.into()),
-        Ok(old_config_desc) => {
-          for interface in 0..old_config_desc.num_interfaces() {
-            if usb.kernel_driver_active(interface)? {
-              handle.reattach_kernel_driver.push(interface);
-              usb.detach_kernel_driver(interface)?;
-            }
-          }
-        }
-      };
-    }
+    handle.set_quirks(quirks)?;
 
-    let new_config = handle.instrument.config_desc.number();
-    if old_config != new_config {
-      handle.restore_config = Some(old_config);
-      usb.set_active_configuration(new_config)?;
+    // Throw away anything left in the bulk-in FIFO by an earlier session that
+    // was interrupted, so that it can't be mistaken for a reply to us.
+    handle.flush_bulk_in()?;
+    if options.get_clear() && !quirks.broken_clear {
+      handle.clear()?;
     }
-
-    usb.claim_interface(endpoints.interface_number)?;
-
-    handle.clear()?;
     handle.get_capabilities()?;
+    handle.set_term_char(options.get_term_char())?;
 
     if let Some(caps) = &handle.usb488_capabilities {
-      if caps.scpi {
-        if let Ok(id_str) = handle.ask("*IDN?") {
+      if caps.scpi && options.get_query_id() {
+        if let Ok(id_str) = handle.query_scpi_id() {
           handle.scpi_id = Some(id_str.trim().to_owned());
         }
       }
@@ -104,12 +143,32 @@ impl<Ctx: UsbContext> InstrumentHandle<Ctx> {
     Ok(handle)
   }
 
+  /// The transport this handle communicates through
+  pub fn transport(&self) -> &T {
+    &self.transport
+  }
+
+  /// The TMC interface this handle communicates with
+  pub fn endpoints(&self) -> &TMCInterface {
+    self.engine.endpoints()
+  }
+
   pub fn get_max_transfer_size(&self) -> u32 {
     self.max_transfer_size
   }
 
-  pub fn set_max_transfer_size(&mut self, max_transfer_size: u32) {
-    self.max_transfer_size = max_transfer_size;
+  /// Set the largest transfer to make, which can't be 0.  This is limited
+  /// to what the instrument's quirks allow.
+  pub fn set_max_transfer_size(&mut self, max_transfer_size: u32) -> TMCResult<()> {
+    if max_transfer_size == 0 {
+      return Err(ClassError::InvalidTransferSize.into());
+    }
+
+    self.max_transfer_size = match self.quirks.max_transfer_size {
+      Some(limit) => max_transfer_size.min(limit),
+      None => max_transfer_size,
+    };
+    Ok(())
   }
 
   pub fn get_term_char(&self) -> Option<u8> {
@@ -121,7 +180,7 @@ impl<Ctx: UsbContext> InstrumentHandle<Ctx> {
       return Err(ClassError::InvalidTermChar.into());
     }
 
-    if term_char.is_some() && !self.usbtmc_capabilities.term_char {
+    if term_char.is_some() && (!self.usbtmc_capabilities.term_char || self.quirks.no_term_char) {
       return Err(ClassError::UnsupportedFeature.into());
     }
 
@@ -137,53 +196,191 @@ impl<Ctx: UsbContext> InstrumentHandle<Ctx> {
     self.timeout = timeout;
   }
 
+  pub fn get_tolerance(&self) -> Tolerance {
+    *self.engine.tolerance()
+  }
+
+  /// Choose which deviations from the spec to accept in responses from the
+  /// instrument.  By default, responses are strictly checked.
+  pub fn set_tolerance(&mut self, tolerance: Tolerance) {
+    self.engine.set_tolerance(tolerance);
+  }
+
+  /// The quirks of this instrument which the handle works around
+  pub fn get_quirks(&self) -> Quirks {
+    self.quirks
+  }
+
+  /// Replace the quirks to work around.  The max transfer size is reduced if
+  /// the new quirks need it; other settings are left alone.
+  pub fn set_quirks(&mut self, quirks: Quirks) -> TMCResult<()> {
+    if quirks.max_transfer_size == Some(0) {
+      return Err(ClassError::InvalidTransferSize.into());
+    }
+
+    self.quirks = quirks;
+    self.engine.set_tolerance(quirks.tolerance);
+    self.set_max_transfer_size(self.max_transfer_size)
+  }
+
+  pub fn get_recovery_policy(&self) -> RecoveryPolicy {
+    self.recovery_policy
+  }
+
+  pub fn set_recovery_policy(&mut self, recovery_policy: RecoveryPolicy) {
+    self.recovery_policy = recovery_policy;
+  }
+
+  pub fn get_local_on_drop(&self) -> bool {
+    self.local_on_drop
+  }
+
+  /// If set, deassert REN when the handle is dropped so that the instrument
+  /// returns to local control and any local lockout is released.  This only
+  /// has an effect on USB488 instruments supporting REN_CONTROL.
+  pub fn set_local_on_drop(&mut self, local_on_drop: bool) {
+    self.local_on_drop = local_on_drop;
+  }
+
   fn read_control(
     &self,
     request: ControlRequest,
     read_size: usize,
     out: &mut Vec<u8>,
   ) -> TMCResult<()> {
-    let request_type = rusb::request_type(
-      rusb::Direction::In,
-      rusb::RequestType::Class,
+    self.read_control_raw(
       rusb::Recipient::Interface,
-    );
-
-    out.resize(read_size, 0);
-    let size = self.usb.read_control(
-      request_type,
-      request as u8,
+      request,
       0x0000,
-      self.instrument.endpoints.interface_number as u16,
+      self.engine.endpoints().interface_number as u16,
+      read_size,
       out,
-      self.timeout,
-    )?;
+    )
+  }
+
+  fn read_control_endpoint(
+    &self,
+    request: ControlRequest,
+    value: u16,
+    endpoint: u8,
+    read_size: usize,
+    out: &mut Vec<u8>,
+  ) -> TMCResult<()> {
+    self.read_control_raw(
+      rusb::Recipient::Endpoint,
+      request,
+      value,
+      endpoint as u16,
+      read_size,
+      out,
+    )
+  }
+
+  fn read_control_raw(
+    &self,
+    recipient: rusb::Recipient,
+    request: ControlRequest,
+    value: u16,
+    index: u16,
+    read_size: usize,
+    out: &mut Vec<u8>,
+  ) -> TMCResult<()> {
+    let request_type = rusb::request_type(rusb::Direction::In, rusb::RequestType::Class, recipient);
+
+    out.resize(read_size, 0);
+    let size =
+      self
+        .transport
+        .read_control(request_type, request as u8, value, index, out, self.timeout)?;
     out.truncate(size);
 
     Ok(())
   }
 
-  // TODO: these messages are defined in the class spec, are they useful?
-  //
-  // I think it might be useful to use abort_bulk_in when connecting to
-  // make sure we don't read stale data if the last connection was interrupted.
-  //
-  // pub fn abort_bulk_out(...)
-  // pub fn abort_bulk_in(...)
+  /// Abort the most recent bulk-out transfer (USBTMC section 4.2.1.2).
+  ///
+  /// This can be used to get the device back into a usable state after a
+  /// `write_raw` fails part-way through, without resorting to a full `clear`.
+  pub fn abort_bulk_out(&mut self) -> TMCResult<()> {
+    let ep = self.engine.endpoints().bulk_out_address;
+
+    let mut out = Vec::with_capacity(8);
+    self.read_control_endpoint(
+      ControlRequest::InitiateAbortBulkOut,
+      self.engine.b_tag() as u16,
+      ep,
+      2,
+      &mut out,
+    )?;
 
-  // Send USBTMC "clear" command
-  pub fn clear(&mut self) -> TMCResult<()> {
-    let mut out = Vec::with_capacity(2);
-    self.read_control(ControlRequest::InitiateClear, 1, &mut out)?;
+    match InitiateAbortResponse::parse(&out)?.status {
+      Status::Success => {}
+      // FAILED means there was no transfer in progress and the bulk-out FIFO
+      // is empty, so there is nothing to abort.
+      Status::Failed => return Ok(()),
+      status => status.check()?,
+    };
 
-    ControlRequest::check_response_status(&out)?;
+    // device accepted `abort` command, wait while status is "pending", but
+    // not forever: a wedged device may never finish
+    let deadline = Instant::now() + self.timeout;
+    loop {
+      self.read_control_endpoint(ControlRequest::CheckAbortBulkOutStatus, 0, ep, 8, &mut out)?;
+
+      match CheckAbortBulkOutStatusResponse::parse(&out)?.status {
+        Status::Success => break,
+        Status::Pending if Instant::now() >= deadline => return Err(rusb::Error::Timeout.into()),
+        Status::Pending => {}
+        status => status.check()?,
+      };
+
+      sleep(Duration::from_millis(100));
+    }
+
+    self.transport.clear_halt(ep)?;
+    Ok(())
+  }
+
+  /// Abort the most recent bulk-in transfer (USBTMC section 4.2.1.4),
+  /// discarding any data the device still has queued for it.
+  ///
+  /// This can be used to recover after a `read_raw` fails part-way through a
+  /// long response, without resorting to a full `clear`.
+  pub fn abort_bulk_in(&mut self) -> TMCResult<()> {
+    let ep = self.engine.endpoints().bulk_in_address;
+
+    let mut out = Vec::with_capacity(8);
+    self.read_control_endpoint(
+      ControlRequest::InitiateAbortBulkIn,
+      self.engine.b_tag() as u16,
+      ep,
+      2,
+      &mut out,
+    )?;
+
+    match InitiateAbortResponse::parse(&out)?.status {
+      Status::Success => {}
+      // FAILED means there was no transfer in progress and the bulk-in FIFO
+      // is empty, so there is nothing to abort.
+      Status::Failed => return Ok(()),
+      status => status.check()?,
+    };
 
-    // device accepted `clear` command, wait while status is "pending"
+    // device accepted `abort` command, read and discard whatever is left of
+    // the transfer, then wait while status is "pending" (but not forever)
+    self.drain_bulk_in()?;
+    let deadline = Instant::now() + self.timeout;
     loop {
-      self.read_control(ControlRequest::CheckClearStatus, 2, &mut out)?;
+      self.read_control_endpoint(ControlRequest::CheckAbortBulkInStatus, 0, ep, 8, &mut out)?;
 
-      match ControlRequest::read_response_status(&out)? {
+      let response = CheckAbortBulkInStatusResponse::parse(&out)?;
+      match response.status {
         Status::Success => break,
+        Status::Pending if Instant::now() >= deadline => return Err(rusb::Error::Timeout.into()),
+        Status::Pending if response.more_data => {
+          self.drain_bulk_in()?;
+          continue;
+        }
         Status::Pending => {}
         status => status.check()?,
       };
@@ -191,12 +388,54 @@ impl<Ctx: UsbContext> InstrumentHandle<Ctx> {
       sleep(Duration::from_millis(100));
     }
 
-    self
-      .usb
-      .clear_halt(self.instrument.endpoints.bulk_out_address)?;
     Ok(())
   }
 
+  /// Read and discard bulk-in data until the device sends a short packet
+  /// (or nothing at all).
+  fn drain_bulk_in(&mut self) -> TMCResult<()> {
+    self.drain_bulk_in_with_timeout(self.timeout)
+  }
+
+  /// Discard any stale data the device has queued on the bulk-in endpoint,
+  /// without waiting long if there isn't any.
+  fn flush_bulk_in(&mut self) -> TMCResult<()> {
+    match self.drain_bulk_in_with_timeout(FLUSH_TIMEOUT) {
+      // an interrupted session may also have left the endpoint halted
+      Err(TMCError::Rusb(rusb::Error::Pipe)) => Ok(
+        self
+          .transport
+          .clear_halt(self.engine.endpoints().bulk_in_address)?,
+      ),
+      result => result,
+    }
+  }
+
+  fn drain_bulk_in_with_timeout(&mut self, timeout: Duration) -> TMCResult<()> {
+    let packet_size = match self.engine.endpoints().bulk_in_max_packet_size {
+      0 => 64,
+      size => size as usize,
+    };
+
+    let mut buf = vec![0u8; packet_size * 64];
+    loop {
+      match self
+        .transport
+        .read_bulk(self.engine.endpoints().bulk_in_address, &mut buf, timeout)
+      {
+        Ok(n_read) if n_read == buf.len() => {}
+        Ok(_) | Err(rusb::Error::Timeout) => return Ok(()),
+        Err(rusb_error) => return Err(rusb_error.into()),
+      }
+    }
+  }
+
+  // Send USBTMC "clear" command
+  pub fn clear(&mut self) -> TMCResult<()> {
+    let op = self.engine.clear();
+    self.run(op)
+  }
+
   fn get_capabilities(&mut self) -> TMCResult<()> {
     // 64 bytes is the largest possible control transfer, so use that to avoid
     // overflow if the device sends a lot back.
@@ -205,7 +444,7 @@ impl<Ctx: UsbContext> InstrumentHandle<Ctx> {
 
     self.usbtmc_capabilities = USBTMCCapabilities::parse(&out)?;
 
-    if self.instrument.endpoints.interface_protocol == 1 {
+    if self.engine.endpoints().interface_protocol == 1 {
       self.usb488_capabilities = USB488Capabilities::parse(&self.usbtmc_capabilities, &out)?;
     }
 
@@ -223,73 +462,384 @@ impl<Ctx: UsbContext> InstrumentHandle<Ctx> {
     Ok(())
   }
 
-  fn incr_b_tag(&mut self) {
-    // bTag must be different on each successive bulk-out transfer and not 0
-    self.b_tag = if self.b_tag == 255 { 1 } else { self.b_tag + 1 };
-  }
+  /// Read the instrument's IEEE 488 status byte (USB488 section 4.3.1).
+  ///
+  /// If the instrument has an interrupt-in endpoint, the status byte is
+  /// delivered there rather than in the control response, so this waits
+  /// for the notification carrying the matching bTag.
+  pub fn read_status_byte(&mut self) -> TMCResult<u8> {
+    self.usb488_capabilities()?;
+
+    // bTag values 2..=127 are reserved for READ_STATUS_BYTE; 1 is used for SRQ
+    self.status_b_tag = if self.status_b_tag >= 127 {
+      2
+    } else {
+      self.status_b_tag + 1
+    };
+    let b_tag = self.status_b_tag;
 
-  /// Write a command message to the instrument
-  pub fn write_raw(&mut self, data: &[u8]) -> TMCResult<()> {
-    let ep = self.instrument.endpoints.bulk_out_address;
+    let mut out = Vec::with_capacity(3);
+    self.read_control_raw(
+      rusb::Recipient::Interface,
+      ControlRequest::ReadStatusByte,
+      b_tag as u16,
+      self.engine.endpoints().interface_number as u16,
+      3,
+      &mut out,
+    )?;
 
-    let mut buf = Vec::with_capacity(HEADER_SIZE + data.len() + 3);
-    let mut end_offset: usize = 0;
+    let response = ReadStatusByteResponse::parse(&out)?;
+    response.status.check()?;
+    if response.b_tag != b_tag {
+      return Err(ClassError::MismatchedTag.into());
+    }
 
-    for block in data.chunks(self.max_transfer_size as usize) {
-      end_offset += block.len();
-      let eom = end_offset >= data.len();
+    if self.engine.endpoints().interrupt_in_address.is_none() {
+      return Ok(response.status_byte);
+    }
 
-      self.incr_b_tag();
-      DevDepMsgOutHeader::encode_message(self.b_tag, data, eom, &mut buf);
+    let deadline = Instant::now() + self.timeout;
+    loop {
+      let timeout = deadline.saturating_duration_since(Instant::now());
+      if timeout == Duration::from_secs(0) {
+        return Err(rusb::Error::Timeout.into());
+      }
 
-      let n_written = self.usb.write_bulk(ep, &buf, self.timeout)?;
-      if n_written < block.len() {
-        return Err(ClassError::TruncatedBulkOut.into());
+      // Queue up anything else that shows up in the meantime (SRQs, replies
+      // to earlier requests that timed out, etc.)
+      match self.read_interrupt(timeout)? {
+        Some(Notification::StatusByte {
+          b_tag: notify_b_tag,
+          status_byte,
+        }) if notify_b_tag == b_tag => return Ok(status_byte),
+        Some(notification) => self.queue_notification(notification),
+        None => {}
       }
     }
+  }
 
-    Ok(())
+  /// Register a function to be called with each notification received on
+  /// the interrupt-in endpoint, or `None` to remove it.
+  ///
+  /// Notifications are only received while the handle is reading the
+  /// interrupt-in endpoint, that is during `read_status_byte`,
+  /// `wait_for_notification` and `poll_notification`.
+  pub fn set_notification_callback(&mut self, callback: Option<NotificationCallback>) {
+    self.notification_callback = callback;
   }
 
-  /// Read response data from the instrument
-  pub fn read_raw(&mut self, transfer_size: Option<u32>) -> TMCResult<Vec<u8>> {
-    let transfer_size = match transfer_size {
-      Some(size) if size < self.max_transfer_size => size,
-      _ => self.max_transfer_size,
+  /// Wait up to `timeout` for a notification from the interrupt-in endpoint.
+  /// Notifications which arrived earlier, while the handle was waiting for
+  /// something else, are returned first.
+  pub fn wait_for_notification(&mut self, timeout: Duration) -> TMCResult<Option<Notification>> {
+    if self.engine.endpoints().interrupt_in_address.is_none() {
+      return Err(ClassError::UnsupportedFeature.into());
+    }
+
+    if let Some(notification) = self.notifications.pop_front() {
+      return Ok(Some(notification));
+    }
+
+    self.read_interrupt(timeout)
+  }
+
+  /// Return a pending notification from the interrupt-in endpoint, if there is one.
+  pub fn poll_notification(&mut self) -> TMCResult<Option<Notification>> {
+    // libusb treats a zero timeout as "wait forever", so use the shortest
+    // one it can represent instead
+    self.wait_for_notification(Duration::from_millis(1))
+  }
+
+  /// Read and decode one packet from the interrupt-in endpoint, passing it
+  /// to the notification callback.  Returns `None` if nothing arrives within
+  /// `timeout`.
+  fn read_interrupt(&mut self, timeout: Duration) -> TMCResult<Option<Notification>> {
+    let ep = match self.engine.endpoints().interrupt_in_address {
+      Some(ep) => ep,
+      None => return Err(ClassError::UnsupportedFeature.into()),
     };
 
-    let mut read_data = Vec::with_capacity(HEADER_SIZE + transfer_size as usize + 3);
+    let mut buf = [0u8; 64];
+    let n_read = match self.transport.read_interrupt(ep, &mut buf, timeout) {
+      Ok(n_read) => n_read,
+      Err(rusb::Error::Timeout) => return Ok(None),
+      Err(rusb_error) => return Err(rusb_error.into()),
+    };
+
+    let usb488 = self.engine.endpoints().interface_protocol == 1;
+    let notification = Notification::parse(&buf[..n_read], usb488)?;
+
+    if let Some(callback) = &mut self.notification_callback {
+      callback(&notification);
+    }
+
+    Ok(Some(notification))
+  }
+
+  fn queue_notification(&mut self, notification: Notification) {
+    if self.notifications.len() >= MAX_QUEUED_NOTIFICATIONS {
+      self.notifications.pop_front();
+    }
+
+    self.notifications.push_back(notification);
+  }
+
+  fn usb488_capabilities(&self) -> TMCResult<&USB488Capabilities> {
+    match &self.usb488_capabilities {
+      Some(caps) => Ok(caps),
+      None => Err(ClassError::UnsupportedFeature.into()),
+    }
+  }
+
+  /// Send a USB488 request whose response is just a status byte
+  fn usb488_request(&self, request: ControlRequest, value: u16) -> TMCResult<()> {
+    let mut out = Vec::with_capacity(1);
+    self.read_control_raw(
+      rusb::Recipient::Interface,
+      request,
+      value,
+      self.engine.endpoints().interface_number as u16,
+      1,
+      &mut out,
+    )?;
+    ControlRequest::check_response_status(&out)?;
+    Ok(())
+  }
+
+  /// Assert or deassert the Remote Enable line (USB488 section 4.3.2).
+  ///
+  /// Deasserting REN returns the instrument to local control and releases
+  /// any local lockout.
+  pub fn ren_control(&self, enable: bool) -> TMCResult<()> {
+    if !self.usb488_capabilities()?.remote_local {
+      return Err(ClassError::UnsupportedFeature.into());
+    }
+
+    self.usb488_request(ControlRequest::RenControl, enable as u16)
+  }
+
+  /// Return the instrument to local control (USB488 section 4.3.3).
+  pub fn go_to_local(&self) -> TMCResult<()> {
+    if !self.usb488_capabilities()?.rl {
+      return Err(ClassError::UnsupportedFeature.into());
+    }
+
+    self.usb488_request(ControlRequest::GoToLocal, 0)
+  }
+
+  /// Disable the instrument's front panel "return to local" control
+  /// (USB488 section 4.3.4).
+  pub fn local_lockout(&self) -> TMCResult<()> {
+    if !self.usb488_capabilities()?.rl {
+      return Err(ClassError::UnsupportedFeature.into());
+    }
+
+    self.usb488_request(ControlRequest::LocalLockout, 0)
+  }
+
+  /// Send a USB488 TRIGGER message (USB488 section 3.2.1.1), which has the
+  /// same effect as a GPIB Group Execute Trigger.
+  pub fn trigger(&mut self) -> TMCResult<()> {
+    let caps = self.usb488_capabilities()?;
+    if !(caps.trigger && caps.dt) {
+      return Err(ClassError::UnsupportedFeature.into());
+    }
+
+    let mut buf = Vec::with_capacity(HEADER_SIZE);
+    TriggerHeader::encode_message(self.engine.next_b_tag(), &mut buf);
+
+    let n_written = self.write_bulk(&buf)?;
+    if n_written < buf.len() {
+      return Err(ClassError::TruncatedBulkOut.into());
+    }
+
+    Ok(())
+  }
+
+  /// Drive a protocol operation to completion
+  fn run<O: Operation>(&mut self, mut op: O) -> TMCResult<O::Output> {
     let mut buf = Vec::new();
+    let mut step = op.step(&mut self.engine, Completion::Start)?;
 
     loop {
-      // Send OUT command header to request device send data
-      self.incr_b_tag();
-      RequestDevDepMsgInHeader::encode_message(self.b_tag, transfer_size, self.term_char, &mut buf);
-      self.usb.write_bulk(
-        self.instrument.endpoints.bulk_out_address,
-        &buf,
-        self.timeout,
-      )?;
-
-      // Read the requested data from the device. Extra space in output buffer is
-      // for the bulk-in header and 3 potential alignment-padding bytes.
-      buf.resize(HEADER_SIZE + transfer_size as usize + 3, 0);
-      let n_read = self.usb.read_bulk(
-        self.instrument.endpoints.bulk_in_address,
-        &mut buf,
-        self.timeout,
-      )?;
-      buf.truncate(n_read);
-
-      let (header, data) = DevDepMsgInHeader::decode_transfer(&buf)?;
-      read_data.extend_from_slice(data);
-
-      if header.is_eom() {
-        break;
+      let action = match step {
+        Step::Done(output) => return Ok(output),
+        Step::Action(action) => action,
+      };
+
+      step = match action {
+        Action::WriteBulk(data) => {
+          let n_written = self.write_bulk(&data)?;
+          op.step(&mut self.engine, Completion::Written(n_written))?
+        }
+        Action::ReadBulk(length) => {
+          buf.resize(length, 0);
+          let n_read = self.read_bulk(&mut buf)?;
+          op.step(&mut self.engine, Completion::Read(&buf[..n_read]))?
+        }
+        Action::DrainBulk(length) => {
+          buf.resize(length, 0);
+          // not through the recovery policy: running out of data is expected
+          let ep = self.engine.endpoints().bulk_in_address;
+          let n_read = match self.transport.read_bulk(ep, &mut buf, self.timeout) {
+            Err(rusb::Error::Timeout) => 0,
+            result => result?,
+          };
+          op.step(&mut self.engine, Completion::Read(&buf[..n_read]))?
+        }
+        Action::ReadControl {
+          request_type,
+          request,
+          value,
+          index,
+          length,
+        } => {
+          buf.resize(length, 0);
+          let n_read = self.transport.read_control(
+            request_type,
+            request,
+            value,
+            index,
+            &mut buf,
+            self.timeout,
+          )?;
+          op.step(&mut self.engine, Completion::Read(&buf[..n_read]))?
+        }
+        Action::ClearHalt(ep) => {
+          self.transport.clear_halt(ep)?;
+          op.step(&mut self.engine, Completion::Done)?
+        }
+        Action::Sleep(duration) => {
+          sleep(duration);
+          op.step(&mut self.engine, Completion::Done)?
+        }
+      };
+    }
+  }
+
+  /// Send a bulk-out transfer, applying the recovery policy if it fails.
+  fn write_bulk(&mut self, buf: &[u8]) -> TMCResult<usize> {
+    if let Some(last_write) = self.last_write {
+      let elapsed = last_write.elapsed();
+      if elapsed < self.quirks.command_delay {
+        sleep(self.quirks.command_delay - elapsed);
+      }
+    }
+
+    let ep = self.engine.endpoints().bulk_out_address;
+    let result = self.transport.write_bulk(ep, buf, self.timeout);
+    self.last_write = Some(Instant::now());
+    self.recover(ep, result)
+  }
+
+  /// Receive a bulk-in transfer, applying the recovery policy if it fails.
+  fn read_bulk(&mut self, buf: &mut [u8]) -> TMCResult<usize> {
+    let ep = self.engine.endpoints().bulk_in_address;
+    let result = self.transport.read_bulk(ep, buf, self.timeout);
+    self.recover(ep, result)
+  }
+
+  fn recover<R>(&mut self, ep: u8, result: rusb::Result<R>) -> TMCResult<R> {
+    let error = match result {
+      Err(error @ rusb::Error::Timeout) | Err(error @ rusb::Error::Pipe) => error,
+      result => return Ok(result?),
+    };
+
+    if self.recovery_policy == RecoveryPolicy::Manual {
+      return Err(error.into());
+    }
+
+    // The endpoint may be halted (and after a timeout, the data toggle may be
+    // out of sync) so clear that first, then abort whatever the device thinks
+    // is still in progress.  The aborted transfer's bTag stays in the engine, so
+    // the next transfer is guaranteed to use a different one.
+    let recovered = self
+      .transport
+      .clear_halt(ep)
+      .map_err(TMCError::from)
+      .and_then(|_| {
+        if ep == self.engine.endpoints().bulk_out_address {
+          self.abort_bulk_out()
+        } else {
+          self.abort_bulk_in()
+        }
+      });
+
+    match recovered {
+      Ok(()) => Err(TMCError::Recovered(error)),
+      // Recovery didn't work either; report the original problem.
+      Err(_) => Err(error.into()),
+    }
+  }
+
+  /// Write a command message to the instrument
+  pub fn write_raw(&mut self, data: &[u8]) -> TMCResult<()> {
+    let op = self.engine.write_message(data, self.max_transfer_size);
+    self.run(op)
+  }
+
+  /// Write a command message to the instrument, streaming its contents from
+  /// `reader` so that the whole message never needs to be in memory at once.
+  /// Returns the number of bytes written.
+  ///
+  /// If reading fails part-way through, the message is not ended, and the
+  /// instrument may be left holding part of it; use `clear` to discard it.
+  pub fn write_raw_from<R: io::Read>(&mut self, mut reader: R) -> io::Result<u64> {
+    let mut writer = self.message_writer();
+    match io::copy(&mut reader, &mut writer) {
+      Ok(n_written) => {
+        writer.end_message()?;
+        Ok(n_written)
+      }
+      Err(error) => {
+        writer.discard();
+        Err(error)
       }
     }
+  }
+
+  /// Write part of a command message, setting EOM at the end only if `eom` is set
+  pub(crate) fn write_message_part(&mut self, data: &[u8], eom: bool) -> TMCResult<()> {
+    let op = self
+      .engine
+      .write_message_part(data, self.max_transfer_size, eom);
+    self.run(op)
+  }
 
-    Ok(read_data)
+  /// Get a [std::io::Write] adapter for streaming a command message to the
+  /// instrument.  See [MessageWriter].
+  pub fn message_writer(&mut self) -> MessageWriter<'_, T> {
+    MessageWriter::new(self)
+  }
+
+  fn clamp_transfer_size(&self, transfer_size: Option<u32>) -> u32 {
+    match transfer_size {
+      Some(size) if size < self.max_transfer_size => size,
+      _ => self.max_transfer_size,
+    }
+  }
+
+  /// Read response data from the instrument
+  pub fn read_raw(&mut self, transfer_size: Option<u32>) -> TMCResult<Vec<u8>> {
+    let transfer_size = self.clamp_transfer_size(transfer_size);
+
+    let op = self.engine.read_message(transfer_size, self.term_char);
+    self.run(op)
+  }
+
+  /// Read a single transfer of response data, returning the data and whether
+  /// it was the end of the message
+  pub(crate) fn read_transfer(&mut self, transfer_size: Option<u32>) -> TMCResult<(Vec<u8>, bool)> {
+    let transfer_size = self.clamp_transfer_size(transfer_size);
+
+    let op = self.engine.read_transfer(transfer_size, self.term_char);
+    self.run(op)
+  }
+
+  /// Get a [std::io::Read] adapter for streaming one response message from
+  /// the instrument, `transfer_size` bytes at a time.  See [MessageReader].
+  pub fn message_reader(&mut self, transfer_size: Option<u32>) -> MessageReader<'_, T> {
+    MessageReader::new(self, transfer_size)
   }
 
   /// Read UTF-8 response data from the instrument
@@ -303,6 +853,19 @@ impl<Ctx: UsbContext> InstrumentHandle<Ctx> {
     self.write_raw(message.as_bytes())
   }
 
+  /// Ask for the instrument's identification string.  Replies whose bTag
+  /// doesn't match our request are left over from an earlier session, so
+  /// those are skipped.
+  fn query_scpi_id(&mut self) -> TMCResult<String> {
+    self.write("*IDN?")?;
+
+    let op = self
+      .engine
+      .read_message(self.max_transfer_size, self.term_char)
+      .skip_stale_replies(MAX_STALE_REPLIES);
+    Ok(String::from_utf8(self.run(op)?)?)
+  }
+
   /// Write a UTF-8 command message to the instrument and read a UTF-8 response
   pub fn ask(&mut self, data: &str) -> TMCResult<String> {
     let response_data = self.ask_raw(data.as_bytes())?;
@@ -316,7 +879,23 @@ impl<Ctx: UsbContext> InstrumentHandle<Ctx> {
     self.read_raw(None)
   }
 
-  // TODO: support for vendor-specific bulk transfers
-  // TODO: support for interrupt in endpoint
+  /// Write vendor-specific data to the instrument (USBTMC section 3.2.1.3)
+  pub fn write_vendor_specific(&mut self, data: &[u8]) -> TMCResult<()> {
+    let op = self
+      .engine
+      .write_vendor_specific(data, self.max_transfer_size);
+    self.run(op)
+  }
+
+  /// Read up to `max_len` bytes of vendor-specific data from the instrument
+  /// (USBTMC section 3.3.1.2).  Vendor-specific transfers have no end-of-message
+  /// marker, so this stops when the device sends less than was requested.
+  pub fn read_vendor_specific(&mut self, max_len: u32) -> TMCResult<Vec<u8>> {
+    let op = self
+      .engine
+      .read_vendor_specific(max_len, self.max_transfer_size);
+    self.run(op)
+  }
+
   // TODO: more complete support for USB488 features
 }
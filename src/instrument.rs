@@ -1,6 +1,12 @@
 use crate::class::*;
-use crate::{InstrumentHandle, TMCResult};
-use rusb;
+use crate::{InstrumentHandle, OpenOptions, ResourceString, TMCResult, UsbTransport};
+use core::time::Duration;
+
+/// Timeout for reading string descriptors
+const STRING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Language ID for US English, preferred when a device offers several
+const LANGUAGE_ENGLISH_US: u16 = 0x0409;
 
 /// Information about an instrument detected on the USB bus.
 ///
@@ -14,25 +20,155 @@ pub struct Instrument<Ctx: rusb::UsbContext> {
   pub config_desc: rusb::ConfigDescriptor,
   pub endpoints: TMCInterface,
 
-  serial_number_loaded: bool,
-  serial_number: Option<String>,
+  serial_number: CachedString,
+  manufacturer: CachedString,
+  product: CachedString,
+  interface_description: CachedString,
 }
 
-impl<Ctx: rusb::UsbContext> Instrument<Ctx> {
-  fn read_serial_number(&mut self) -> TMCResult<Option<String>> {
-    if !self.serial_number_loaded {
-      self.serial_number = match self.device_desc.serial_number_string_index() {
+/// A string descriptor, read from the device the first time it's wanted
+#[derive(Debug, Clone, Default)]
+struct CachedString {
+  loaded: bool,
+  value: Option<String>,
+}
+
+impl CachedString {
+  fn loaded(value: Option<String>) -> Self {
+    Self {
+      loaded: true,
+      value,
+    }
+  }
+
+  fn read<Ctx: rusb::UsbContext>(
+    &mut self,
+    device: &rusb::Device<Ctx>,
+    index: Option<u8>,
+  ) -> TMCResult<Option<String>> {
+    if !self.loaded {
+      self.value = match index {
         None => None,
-        Some(index) => Some(self.device.open()?.read_string_descriptor_ascii(index)?),
+        Some(index) => Some(read_string_descriptor(device, index)?),
       };
 
-      self.serial_number_loaded = true;
+      self.loaded = true;
     }
 
-    Ok(self.serial_number.clone())
+    Ok(self.value.clone())
   }
+}
 
-  /// Get the device's resource string; this may involve connecting to it in order to read its serial number.
+/// Read a string descriptor in the device's preferred language, or US
+/// English if it's one of the choices
+fn read_string_descriptor<Ctx: rusb::UsbContext>(
+  device: &rusb::Device<Ctx>,
+  index: u8,
+) -> TMCResult<String> {
+  let usb = device.open()?;
+
+  let languages = usb.read_languages(STRING_TIMEOUT)?;
+  let language = languages
+    .iter()
+    .find(|language| language.lang_id() == LANGUAGE_ENGLISH_US)
+    .or_else(|| languages.first())
+    .ok_or(rusb::Error::NotFound)?;
+
+  Ok(usb.read_string_descriptor(*language, index, STRING_TIMEOUT)?)
+}
+
+/// Identifies one TMC interface of a device while it stays plugged in.  A
+/// device gets a new address each time it's connected, so this doesn't
+/// identify it across reconnects.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct InstrumentId {
+  pub bus_number: u8,
+  pub address: u8,
+  pub interface_number: u8,
+  pub alternate_setting: u8,
+}
+
+impl<Ctx: rusb::UsbContext> Instrument<Ctx> {
+  pub fn id(&self) -> InstrumentId {
+    InstrumentId {
+      bus_number: self.device.bus_number(),
+      address: self.device.address(),
+      interface_number: self.endpoints.interface_number,
+      alternate_setting: self.endpoints.alternate_setting,
+    }
+  }
+
+  /// Get the device's serial number; this may involve connecting to it.
+  pub fn read_serial_number(&mut self) -> TMCResult<Option<String>> {
+    let index = self.device_desc.serial_number_string_index();
+    self.serial_number.read(&self.device, index)
+  }
+
+  /// Get the device's manufacturer string; this may involve connecting to it.
+  pub fn read_manufacturer(&mut self) -> TMCResult<Option<String>> {
+    let index = self.device_desc.manufacturer_string_index();
+    self.manufacturer.read(&self.device, index)
+  }
+
+  /// Get the device's product string; this may involve connecting to it.
+  pub fn read_product(&mut self) -> TMCResult<Option<String>> {
+    let index = self.device_desc.product_string_index();
+    self.product.read(&self.device, index)
+  }
+
+  /// Get the description string of the TMC interface; this may involve
+  /// connecting to the device.
+  pub fn read_interface_description(&mut self) -> TMCResult<Option<String>> {
+    let endpoints = &self.endpoints;
+    let index = self
+      .config_desc
+      .interfaces()
+      .flat_map(|interface| interface.descriptors())
+      .find(|interface_desc| {
+        interface_desc.interface_number() == endpoints.interface_number
+          && interface_desc.setting_number() == endpoints.alternate_setting
+      })
+      .and_then(|interface_desc| interface_desc.description_string_index());
+
+    self.interface_description.read(&self.device, index)
+  }
+
+  /// Fill in the strings read by other means than asking the device
+  pub(crate) fn preload_strings(
+    &mut self,
+    serial_number: Option<String>,
+    manufacturer: Option<String>,
+    product: Option<String>,
+    interface_description: Option<String>,
+  ) {
+    self.serial_number = CachedString::loaded(serial_number);
+    self.manufacturer = CachedString::loaded(manufacturer);
+    self.product = CachedString::loaded(product);
+    self.interface_description = CachedString::loaded(interface_description);
+  }
+
+  /// Get the device's VISA resource string; this may involve connecting to it
+  /// in order to read its serial number.
+  pub fn resource_string(&mut self) -> TMCResult<ResourceString> {
+    let interface_number = match self.endpoints.interface_number {
+      0 => None,
+      interface_number => Some(interface_number),
+    };
+
+    Ok(ResourceString {
+      board: 0,
+      vendor_id: self.device_desc.vendor_id(),
+      product_id: self.device_desc.product_id(),
+      serial_number: self.read_serial_number()?,
+      interface_number,
+    })
+  }
+
+  /// Get the device's resource string in this crate's older format,
+  /// `USB::<vendor>::<product>[::<serial>]::INSTR` with the IDs in decimal;
+  /// this may involve connecting to it in order to read its serial number.
+  /// It can still be parsed as a [ResourceString], but
+  /// [Instrument::resource_string] gives the VISA form.
   pub fn read_resource_string(&mut self) -> TMCResult<String> {
     let vendor_id = self.device_desc.vendor_id();
     let product_id = self.device_desc.product_id();
@@ -46,115 +182,161 @@ impl<Ctx: rusb::UsbContext> Instrument<Ctx> {
     }
   }
 
-  pub fn open(mut self) -> TMCResult<InstrumentHandle<Ctx>> {
-    self.read_serial_number()?;
+  /// Find the TMC interface with a given number and alternate setting in
+  /// the instrument's configuration
+  pub fn find_interface(
+    &self,
+    interface_number: u8,
+    alternate_setting: u8,
+  ) -> Option<TMCInterface> {
+    self
+      .config_desc
+      .interfaces()
+      .flat_map(|interface| interface.descriptors())
+      .find(|interface_desc| {
+        interface_desc.interface_number() == interface_number
+          && interface_desc.setting_number() == alternate_setting
+      })
+      .and_then(|interface_desc| tmc_interface(&interface_desc))
+  }
 
-    InstrumentHandle::connect(self)
+  /// Open the instrument with default [OpenOptions]
+  pub fn open(self) -> TMCResult<InstrumentHandle<UsbTransport<Ctx>>> {
+    OpenOptions::new().open(self)
   }
 }
 
-fn is_usbtmc_device<Ctx: rusb::UsbContext>(
-  device: rusb::Device<Ctx>,
-) -> TMCResult<Option<Instrument<Ctx>>> {
-  let device_desc = device.device_descriptor()?;
+/// Describe an interface's endpoints, if it's a usable TMC interface
+fn tmc_interface(interface_desc: &rusb::InterfaceDescriptor) -> Option<TMCInterface> {
+  if interface_desc.class_code() != 0xFE || interface_desc.sub_class_code() != 3 {
+    return None;
+  }
+
+  let mut control_in_max_packet_size: u16 = 0;
+  let mut bulk_in_max_packet_size: u16 = 0;
+  let mut bulk_in_address: Option<u8> = None;
+  let mut bulk_out_max_packet_size: u16 = 0;
+  let mut bulk_out_address: Option<u8> = None;
+  let mut interrupt_in_address: Option<u8> = None;
+
+  for ep_desc in interface_desc.endpoint_descriptors() {
+    use rusb::Direction::*;
+    use rusb::TransferType::*;
+
+    match (ep_desc.transfer_type(), ep_desc.direction()) {
+      (Control, In) => {
+        control_in_max_packet_size = ep_desc.max_packet_size();
+      }
+      (Bulk, In) => {
+        bulk_in_address = Some(ep_desc.address());
+        bulk_in_max_packet_size = ep_desc.max_packet_size();
+      }
+      (Bulk, Out) => {
+        bulk_out_address = Some(ep_desc.address());
+        bulk_out_max_packet_size = ep_desc.max_packet_size();
+      }
+      (Interrupt, In) => {
+        interrupt_in_address = Some(ep_desc.address());
+      }
+      (_, _) => {
+        // ignore extra endpoints
+      }
+    }
+  }
+
+  Some(TMCInterface {
+    interface_number: interface_desc.interface_number(),
+    alternate_setting: interface_desc.setting_number(),
+    interface_protocol: interface_desc.protocol_code(),
+    control_in_max_packet_size,
+    bulk_in_address: bulk_in_address?,
+    bulk_in_max_packet_size,
+    bulk_out_address: bulk_out_address?,
+    bulk_out_max_packet_size,
+    interrupt_in_address,
+  })
+}
+
+/// Find all the TMC interfaces of a device, in any configuration or
+/// alternate setting.  Each [Instrument] needs its own reference to the
+/// device, and rusb can't clone a [rusb::Device], so `device` gets a new one
+/// for each call.
+fn usbtmc_interfaces<Ctx: rusb::UsbContext>(
+  device: impl Fn() -> rusb::Device<Ctx>,
+  read_serial_number: bool,
+) -> TMCResult<Vec<Instrument<Ctx>>> {
+  let device_desc = device().device_descriptor()?;
+  let mut instruments = Vec::new();
 
   for cfg_id in 0..device_desc.num_configurations() {
-    let config_desc = match device.config_descriptor(cfg_id) {
+    let config_desc = match device().config_descriptor(cfg_id) {
       Err(_) => continue,
       Ok(desc) => desc,
     };
 
-    let mut found_interface: Option<TMCInterface> = None;
     for interface in config_desc.interfaces() {
       for interface_desc in interface.descriptors() {
-        if interface_desc.class_code() == 0xFE && interface_desc.sub_class_code() == 3 {
-          let mut control_in_max_packet_size: u16 = 0;
-          let mut bulk_in_max_packet_size: u16 = 0;
-          let mut bulk_in_address: Option<u8> = None;
-          let mut bulk_out_max_packet_size: u16 = 0;
-          let mut bulk_out_address: Option<u8> = None;
-          let mut interrupt_in_address: Option<u8> = None;
-
-          for ep_desc in interface_desc.endpoint_descriptors() {
-            use rusb::Direction::*;
-            use rusb::TransferType::*;
-
-            match (ep_desc.transfer_type(), ep_desc.direction()) {
-              (Control, In) => {
-                control_in_max_packet_size = ep_desc.max_packet_size();
-              }
-              (Bulk, In) => {
-                bulk_in_address = Some(ep_desc.address());
-                bulk_in_max_packet_size = ep_desc.max_packet_size();
-              }
-              (Bulk, Out) => {
-                bulk_out_address = Some(ep_desc.address());
-                bulk_out_max_packet_size = ep_desc.max_packet_size();
-              }
-              (Interrupt, In) => {
-                interrupt_in_address = Some(ep_desc.address());
-              }
-              (_, _) => {
-                // ignore extra endpoints
-              }
-            }
-          }
-
-          if let (Some(bulk_in_address), Some(bulk_out_address)) =
-            (bulk_in_address, bulk_out_address)
-          {
-            found_interface = Some(TMCInterface {
-              interface_number: interface_desc.interface_number(),
-              interface_protocol: interface_desc.protocol_code(),
-              control_in_max_packet_size,
-              bulk_in_address,
-              bulk_in_max_packet_size,
-              bulk_out_address,
-              bulk_out_max_packet_size,
-              interrupt_in_address,
-            });
-
-            break;
-          }
-        }
-      }
+        if let Some(endpoints) = tmc_interface(&interface_desc) {
+          instruments.push(Instrument {
+            device: device(),
+            device_desc: device().device_descriptor()?,
+            config_desc: device().config_descriptor(cfg_id)?,
+            endpoints,
 
-      if found_interface.is_some() {
-        break;
+            serial_number: CachedString::default(),
+            manufacturer: CachedString::default(),
+            product: CachedString::default(),
+            interface_description: CachedString::default(),
+          });
+        }
       }
     }
+  }
 
-    if let Some(endpoints) = found_interface {
-      let mut instrument = Instrument {
-        device,
-        device_desc,
-        config_desc,
-        endpoints,
-
-        serial_number_loaded: false,
-        serial_number: None,
-      };
-
-      // Try to read the serial number; this will attempt to connect, but we don't mind
-      // if it fails.
-      let _ = instrument.read_serial_number();
+  // Try to read the serial number; this will attempt to connect, but we don't mind
+  // if it fails.  All the interfaces share it, so it's only read once.
+  if let Some((first, rest)) = instruments.split_first_mut().filter(|_| read_serial_number) {
+    let _ = first.read_serial_number();
 
-      return Ok(Some(instrument));
+    for instrument in rest {
+      instrument.serial_number = first.serial_number.clone();
     }
   }
 
-  Ok(None)
+  Ok(instruments)
 }
 
-/// List detected USBTMC devices
+/// List detected USBTMC devices.  A device with several TMC interfaces, or
+/// with TMC interfaces on several alternate settings, is listed once for each.
 pub fn list_instruments<Ctx: rusb::UsbContext>(context: Ctx) -> TMCResult<Vec<Instrument<Ctx>>> {
+  list_instruments_where(context, |_| true)
+}
+
+/// List detected USBTMC devices, looking only at the devices `filter` accepts
+pub(crate) fn list_instruments_where<Ctx: rusb::UsbContext>(
+  context: Ctx,
+  filter: impl Fn(&rusb::Device<Ctx>) -> bool,
+) -> TMCResult<Vec<Instrument<Ctx>>> {
+  list_instruments_with(context, filter, true)
+}
+
+/// List detected USBTMC devices, looking only at the devices `filter`
+/// accepts, and only opening them to read serial numbers if asked to
+pub(crate) fn list_instruments_with<Ctx: rusb::UsbContext>(
+  context: Ctx,
+  filter: impl Fn(&rusb::Device<Ctx>) -> bool,
+  read_serial_number: bool,
+) -> TMCResult<Vec<Instrument<Ctx>>> {
   let all_devices = context.devices()?;
   let mut usbtmc_devices = Vec::new();
 
-  for device in all_devices.iter() {
-    if let Some(device) = is_usbtmc_device(device)? {
-      usbtmc_devices.push(device);
+  for (index, device) in all_devices.iter().enumerate() {
+    if !filter(&device) {
+      continue;
     }
+
+    let device = || all_devices.iter().nth(index).expect("device list changed");
+    usbtmc_devices.extend(usbtmc_interfaces(device, read_serial_number)?);
   }
 
   Ok(usbtmc_devices)
@@ -1,9 +1,34 @@
 pub mod class;
+pub mod protocol;
 
+#[cfg(feature = "tokio")]
+mod async_handle;
 mod error;
 mod handle;
 mod instrument;
+mod options;
+mod query;
+mod quirks;
+mod reconnect;
+mod resource;
+mod stream;
+#[cfg(target_os = "linux")]
+mod sysfs;
+mod transport;
+mod watcher;
 
+#[cfg(feature = "tokio")]
+pub use async_handle::*;
 pub use error::*;
 pub use handle::*;
 pub use instrument::*;
+pub use options::*;
+pub use query::*;
+pub use quirks::*;
+pub use reconnect::*;
+pub use resource::*;
+pub use stream::*;
+#[cfg(target_os = "linux")]
+pub use sysfs::*;
+pub use transport::*;
+pub use watcher::*;
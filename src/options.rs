@@ -0,0 +1,179 @@
+use crate::{Instrument, InstrumentHandle, Quirks, TMCResult, UsbTransport};
+use core::time::Duration;
+
+/// Settings controlling how an instrument is opened.
+///
+/// [Instrument::open] uses the defaults: kernel drivers are detached, the
+/// instrument is sent CLEAR, and `*IDN?` is queried if it speaks SCPI.
+/// Build an `OpenOptions` to change any of that:
+///
+/// ```no_run
+/// # fn main() -> tmc::TMCResult<()> {
+/// # let context = rusb::Context::new()?;
+/// # let instrument = tmc::list_instruments(context)?.remove(0);
+/// let handle = tmc::OpenOptions::new()
+///   .clear(false)
+///   .query_id(false)
+///   .open(instrument)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OpenOptions {
+  clear: bool,
+  query_id: bool,
+  detach_kernel_driver: bool,
+  timeout: Duration,
+  max_transfer_size: u32,
+  term_char: Option<u8>,
+  interface_number: Option<u8>,
+  alternate_setting: Option<u8>,
+  quirks: Option<Quirks>,
+}
+
+impl Default for OpenOptions {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl OpenOptions {
+  pub fn new() -> Self {
+    Self {
+      clear: true,
+      query_id: true,
+      detach_kernel_driver: true,
+      timeout: Duration::from_secs(1),
+      max_transfer_size: 1024 * 1024,
+      term_char: None,
+      interface_number: None,
+      alternate_setting: None,
+      quirks: None,
+    }
+  }
+
+  /// Whether to send USBTMC CLEAR when connecting.  Some instruments reset
+  /// their measurement state on CLEAR, so it can be turned off.
+  pub fn clear(&mut self, clear: bool) -> &mut Self {
+    self.clear = clear;
+    self
+  }
+
+  /// Whether to query `*IDN?` when connecting to a SCPI instrument.
+  pub fn query_id(&mut self, query_id: bool) -> &mut Self {
+    self.query_id = query_id;
+    self
+  }
+
+  /// Whether kernel drivers bound to the device may be detached.  If not,
+  /// opening fails with [rusb::Error::Busy] when a kernel driver has the TMC
+  /// interface, or has any interface and the configuration must change.
+  pub fn detach_kernel_driver(&mut self, detach_kernel_driver: bool) -> &mut Self {
+    self.detach_kernel_driver = detach_kernel_driver;
+    self
+  }
+
+  /// Timeout for each transfer, starting with those made while connecting
+  pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+    self.timeout = timeout;
+    self
+  }
+
+  /// Largest transfer to make.  Opening fails if this is 0.
+  pub fn max_transfer_size(&mut self, max_transfer_size: u32) -> &mut Self {
+    self.max_transfer_size = max_transfer_size;
+    self
+  }
+
+  /// Terminating character for responses.  Opening fails if the instrument
+  /// doesn't support one.
+  pub fn term_char(&mut self, term_char: Option<u8>) -> &mut Self {
+    self.term_char = term_char;
+    self
+  }
+
+  /// Claim this TMC interface, rather than the one the [Instrument] describes.
+  pub fn interface_number(&mut self, interface_number: u8) -> &mut Self {
+    self.interface_number = Some(interface_number);
+    self
+  }
+
+  /// Select this alternate setting of the TMC interface after claiming it,
+  /// rather than the one the [Instrument] describes.
+  pub fn alternate_setting(&mut self, alternate_setting: u8) -> &mut Self {
+    self.alternate_setting = Some(alternate_setting);
+    self
+  }
+
+  /// Work around these quirks, instead of the ones registered for the
+  /// instrument's vendor and product ID.
+  pub fn quirks(&mut self, quirks: Quirks) -> &mut Self {
+    self.quirks = Some(quirks);
+    self
+  }
+
+  pub fn get_clear(&self) -> bool {
+    self.clear
+  }
+
+  pub fn get_query_id(&self) -> bool {
+    self.query_id
+  }
+
+  pub fn get_detach_kernel_driver(&self) -> bool {
+    self.detach_kernel_driver
+  }
+
+  pub fn get_timeout(&self) -> Duration {
+    self.timeout
+  }
+
+  pub fn get_max_transfer_size(&self) -> u32 {
+    self.max_transfer_size
+  }
+
+  pub fn get_term_char(&self) -> Option<u8> {
+    self.term_char
+  }
+
+  pub fn get_interface_number(&self) -> Option<u8> {
+    self.interface_number
+  }
+
+  pub fn get_alternate_setting(&self) -> Option<u8> {
+    self.alternate_setting
+  }
+
+  pub fn get_quirks(&self) -> Option<Quirks> {
+    self.quirks
+  }
+
+  /// Open an instrument with these options
+  pub fn open<Ctx: rusb::UsbContext>(
+    &self,
+    mut instrument: Instrument<Ctx>,
+  ) -> TMCResult<InstrumentHandle<UsbTransport<Ctx>>> {
+    if self.interface_number.is_some() || self.alternate_setting.is_some() {
+      let interface_number = self
+        .interface_number
+        .unwrap_or(instrument.endpoints.interface_number);
+      let alternate_setting = match self.alternate_setting {
+        Some(alternate_setting) => alternate_setting,
+        None if interface_number == instrument.endpoints.interface_number => {
+          instrument.endpoints.alternate_setting
+        }
+        None => 0,
+      };
+
+      instrument.endpoints = instrument
+        .find_interface(interface_number, alternate_setting)
+        .ok_or(rusb::Error::NotFound)?;
+    }
+
+    instrument.read_serial_number()?;
+
+    let endpoints = instrument.endpoints.clone();
+    let transport = UsbTransport::open_with(instrument, self)?;
+    InstrumentHandle::connect_with(transport, endpoints, self)
+  }
+}
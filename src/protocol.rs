@@ -0,0 +1,1002 @@
+//! A sans-IO implementation of the USBTMC message exchanges.
+//!
+//! Each exchange (writing a message, reading a response, clearing the
+//! device...) is an [Operation]: a state machine which asks for I/O to be
+//! done by returning an [Action], and is advanced by passing the result
+//! back in as a [Completion].  The operations never touch a USB device
+//! themselves, so the same implementation of bTag sequencing, EOM handling
+//! and status polling can be driven by a blocking handle, an async one, or
+//! a simulated device in a test.
+//!
+//! A driver loop looks roughly like:
+//!
+//! ```ignore
+//! let mut step = op.step(&mut engine, Completion::Start)?;
+//! loop {
+//!   step = match step {
+//!     Step::Done(output) => return Ok(output),
+//!     Step::Action(Action::WriteBulk(data)) => {
+//!       let n = usb.write_bulk(engine.endpoints().bulk_out_address, &data, timeout)?;
+//!       op.step(&mut engine, Completion::Written(n))?
+//!     }
+//!     // ...
+//!   };
+//! }
+//! ```
+
+use crate::class::*;
+use core::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Connection state shared by all operations on one TMC interface.
+#[derive(Debug, Clone)]
+pub struct Engine {
+  endpoints: TMCInterface,
+  b_tag: u8,
+  tolerance: Tolerance,
+}
+
+impl Engine {
+  pub fn new(endpoints: TMCInterface) -> Self {
+    // Start each connection somewhere different in the bTag sequence, so that
+    // a response left over from a previous one is unlikely to match.
+    let seed = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|time| time.subsec_nanos())
+      .unwrap_or(0);
+
+    Self {
+      endpoints,
+      b_tag: (seed % 255) as u8,
+      tolerance: Tolerance::default(),
+    }
+  }
+
+  pub fn endpoints(&self) -> &TMCInterface {
+    &self.endpoints
+  }
+
+  /// The bTag of the most recent bulk-out transfer
+  pub fn b_tag(&self) -> u8 {
+    self.b_tag
+  }
+
+  /// Deviations from the spec accepted when decoding responses
+  pub fn tolerance(&self) -> &Tolerance {
+    &self.tolerance
+  }
+
+  pub fn set_tolerance(&mut self, tolerance: Tolerance) {
+    self.tolerance = tolerance;
+  }
+
+  /// Allocate the bTag for a new bulk-out transfer
+  pub fn next_b_tag(&mut self) -> u8 {
+    // bTag must be different on each successive bulk-out transfer and not 0
+    self.b_tag = if self.b_tag == 255 { 1 } else { self.b_tag + 1 };
+    self.b_tag
+  }
+
+  /// Build an action for a class-specific control-in request to the TMC interface
+  pub fn interface_request(&self, request: ControlRequest, value: u16, length: usize) -> Action {
+    Action::ReadControl {
+      request_type: rusb::request_type(
+        rusb::Direction::In,
+        rusb::RequestType::Class,
+        rusb::Recipient::Interface,
+      ),
+      request: request.into(),
+      value,
+      index: self.endpoints.interface_number as u16,
+      length,
+    }
+  }
+
+  /// Round a bulk-in read up to a whole number of packets, so that a device
+  /// sending more than expected can't overflow the buffer part-way through one.
+  fn bulk_in_read_size(&self, length: usize) -> usize {
+    match self.endpoints.bulk_in_max_packet_size as usize {
+      0 => length,
+      packet_size => length.div_ceil(packet_size) * packet_size,
+    }
+  }
+
+  /// Start writing a device-dependent message, split into transfers of at
+  /// most `max_transfer_size` bytes
+  pub fn write_message<'d>(&self, data: &'d [u8], max_transfer_size: u32) -> WriteMessage<'d> {
+    self.write_message_part(data, max_transfer_size, true)
+  }
+
+  /// Start writing part of a device-dependent message.  The EOM bit is only
+  /// set on the last transfer, and only if `eom` is set.
+  pub fn write_message_part<'d>(
+    &self,
+    data: &'d [u8],
+    max_transfer_size: u32,
+    eom: bool,
+  ) -> WriteMessage<'d> {
+    WriteMessage {
+      kind: MessageKind::DevDep,
+      data,
+      max_transfer_size,
+      eom,
+      end_offset: 0,
+      frame_len: 0,
+    }
+  }
+
+  /// Start writing vendor-specific data, split into transfers of at most
+  /// `max_transfer_size` bytes
+  pub fn write_vendor_specific<'d>(
+    &self,
+    data: &'d [u8],
+    max_transfer_size: u32,
+  ) -> WriteMessage<'d> {
+    WriteMessage {
+      kind: MessageKind::VendorSpecific,
+      ..self.write_message(data, max_transfer_size)
+    }
+  }
+
+  /// Start reading a device-dependent message, requesting at most
+  /// `transfer_size` bytes at a time
+  pub fn read_message(&self, transfer_size: u32, term_char: Option<u8>) -> ReadMessage {
+    ReadMessage {
+      transfer: self.read_transfer(transfer_size, term_char),
+      data: Vec::new(),
+    }
+  }
+
+  /// Start reading a single transfer of up to `transfer_size` bytes of a
+  /// device-dependent message.  The output is the data and whether the
+  /// transfer had the EOM bit set.
+  pub fn read_transfer(&self, transfer_size: u32, term_char: Option<u8>) -> ReadTransfer {
+    ReadTransfer {
+      kind: MessageKind::DevDep,
+      transfer_size,
+      term_char,
+      b_tag: 0,
+      stale_replies: 0,
+      received: Vec::new(),
+      requested: 0,
+      state: ReadState::Request,
+    }
+  }
+
+  /// Start reading up to `max_len` bytes of vendor-specific data, requesting
+  /// at most `max_transfer_size` bytes at a time.  Vendor-specific transfers
+  /// have no end-of-message marker, so this stops when the device sends less
+  /// than was requested.
+  pub fn read_vendor_specific(&self, max_len: u32, max_transfer_size: u32) -> ReadVendorSpecific {
+    ReadVendorSpecific {
+      max_len,
+      max_transfer_size,
+      transfer: None,
+      data: Vec::new(),
+    }
+  }
+
+  fn read_vendor_specific_transfer(&self, transfer_size: u32) -> ReadTransfer {
+    ReadTransfer {
+      kind: MessageKind::VendorSpecific,
+      ..self.read_transfer(transfer_size, None)
+    }
+  }
+
+  /// Start clearing the device's input and output buffers
+  pub fn clear(&self) -> Clear {
+    Clear {
+      state: ClearState::Initiate,
+      requested: 0,
+    }
+  }
+}
+
+/// I/O requested by an [Operation]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Action {
+  /// Send these bytes to the bulk-out endpoint; complete with [Completion::Written]
+  WriteBulk(Vec<u8>),
+
+  /// Read up to this many bytes from the bulk-in endpoint; complete with [Completion::Read]
+  ReadBulk(usize),
+
+  /// Read up to this many bytes of leftover data from the bulk-in endpoint;
+  /// complete with [Completion::Read].  A timeout means there is nothing left
+  /// and completes with an empty read.
+  DrainBulk(usize),
+
+  /// Issue this control-in request; complete with [Completion::Read]
+  ReadControl {
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    length: usize,
+  },
+
+  /// Clear the halt condition on this endpoint; complete with [Completion::Done]
+  ClearHalt(u8),
+
+  /// Wait this long before continuing; complete with [Completion::Done]
+  Sleep(Duration),
+}
+
+/// The result of an [Action], fed back into the [Operation] which asked for it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Completion<'a> {
+  /// Nothing has happened yet; used for the first step of an operation
+  Start,
+
+  /// A bulk-out write completed, sending this many bytes
+  Written(usize),
+
+  /// A bulk-in or control-in read completed with this data
+  Read(&'a [u8]),
+
+  /// A clear-halt or sleep completed
+  Done,
+}
+
+/// What an [Operation] wants to happen next
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Step<T> {
+  Action(Action),
+  Done(T),
+}
+
+/// A USBTMC exchange, written as a state machine
+pub trait Operation {
+  type Output;
+
+  /// Advance the operation with the result of its previous action
+  /// ([Completion::Start] the first time).
+  fn step(
+    &mut self,
+    engine: &mut Engine,
+    completion: Completion,
+  ) -> Result<Step<Self::Output>, ClassError>;
+}
+
+/// Whether a transfer carries a device-dependent message or vendor-specific data
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum MessageKind {
+  DevDep,
+  VendorSpecific,
+}
+
+/// See [Engine::write_message]
+#[derive(Debug, Clone)]
+pub struct WriteMessage<'d> {
+  kind: MessageKind,
+  data: &'d [u8],
+  max_transfer_size: u32,
+  eom: bool,
+  end_offset: usize,
+  frame_len: usize,
+}
+
+impl<'d> Operation for WriteMessage<'d> {
+  type Output = ();
+
+  fn step(&mut self, engine: &mut Engine, completion: Completion) -> Result<Step<()>, ClassError> {
+    if self.max_transfer_size == 0 {
+      return Err(ClassError::InvalidTransferSize);
+    }
+
+    if let Completion::Written(n_written) = completion {
+      if n_written < self.frame_len {
+        return Err(ClassError::TruncatedBulkOut);
+      }
+    }
+
+    let block = match self.data[self.end_offset..]
+      .chunks(self.max_transfer_size as usize)
+      .next()
+    {
+      None => return Ok(Step::Done(())),
+      Some(block) => block,
+    };
+
+    self.end_offset += block.len();
+    let eom = self.eom && self.end_offset >= self.data.len();
+
+    let mut buf = Vec::with_capacity(HEADER_SIZE + block.len() + 3);
+    match self.kind {
+      MessageKind::DevDep => {
+        DevDepMsgOutHeader::encode_message(engine.next_b_tag(), block, eom, &mut buf)
+      }
+      MessageKind::VendorSpecific => {
+        VendorSpecificOutHeader::encode_message(engine.next_b_tag(), block, &mut buf)
+      }
+    }
+
+    // the whole frame, including header and alignment padding, must be sent
+    self.frame_len = buf.len();
+    Ok(Step::Action(Action::WriteBulk(buf)))
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum ReadState {
+  Request,
+  Response,
+}
+
+/// See [Engine::read_transfer]
+#[derive(Debug, Clone)]
+pub struct ReadTransfer {
+  kind: MessageKind,
+  transfer_size: u32,
+  term_char: Option<u8>,
+  b_tag: u8,
+  stale_replies: usize,
+  received: Vec<u8>,
+  requested: usize,
+  state: ReadState,
+}
+
+impl Operation for ReadTransfer {
+  type Output = (Vec<u8>, bool);
+
+  fn step(
+    &mut self,
+    engine: &mut Engine,
+    completion: Completion,
+  ) -> Result<Step<(Vec<u8>, bool)>, ClassError> {
+    match (self.state, completion) {
+      (ReadState::Response, Completion::Written(_)) => {
+        // Read the requested data from the device. Extra space in output buffer is
+        // for the bulk-in header and 3 potential alignment-padding bytes.
+        Ok(self.read_more(engine, HEADER_SIZE + self.transfer_size as usize + 3))
+      }
+      (ReadState::Response, Completion::Read(buf)) => {
+        // A short packet means the device has finished sending, for now
+        let short = buf.len() < self.requested;
+        self.received.extend_from_slice(buf);
+
+        if self.received.len() < HEADER_SIZE {
+          let remaining = HEADER_SIZE + self.transfer_size as usize + 3 - self.received.len();
+          return Ok(self.read_more(engine, remaining));
+        }
+
+        // The device may send the header and data in separate pieces, so keep
+        // reading until everything the header announced has arrived.  The
+        // alignment padding is only waited for if the device hasn't already
+        // signalled the end of the transfer with a short packet.
+        let header = DevDepMsgInHeader::unpack(&self.received)?;
+        if header.transfer_size > self.transfer_size && !engine.tolerance.oversize_transfer {
+          return Err(ClassError::OversizeTransfer);
+        }
+
+        let data_len = HEADER_SIZE + header.transfer_size as usize;
+        // whatever a tolerated oversize header says, never read more than the
+        // request allowed for
+        let limit = engine.bulk_in_read_size(HEADER_SIZE + self.transfer_size as usize + 3);
+        let padded_len = ((data_len + 3) & !3).min(limit);
+        let received = self.received.len();
+        let complete = received >= padded_len
+          || (short && received >= data_len)
+          || (short && engine.tolerance.oversize_transfer);
+
+        if !complete {
+          return Ok(self.read_more(engine, padded_len - received));
+        }
+
+        let (b_tag, data, eom) = self.decode(&engine.tolerance)?;
+        if b_tag != self.b_tag && self.stale_replies > 0 && !engine.tolerance.mismatched_tag {
+          // an answer to an earlier request; ours is still to come
+          self.stale_replies -= 1;
+          self.received.clear();
+          return Ok(self.read_more(engine, HEADER_SIZE + self.transfer_size as usize + 3));
+        }
+        engine.tolerance.check_tag(self.b_tag, b_tag)?;
+        let result = (data, eom);
+
+        // ready to be reused for the next transfer
+        self.state = ReadState::Request;
+        Ok(Step::Done(result))
+      }
+      _ => {
+        // Send OUT command header to request device send data
+        self.b_tag = engine.next_b_tag();
+        self.received.clear();
+
+        let mut buf = Vec::with_capacity(HEADER_SIZE);
+        match self.kind {
+          MessageKind::DevDep => RequestDevDepMsgInHeader::encode_message(
+            self.b_tag,
+            self.transfer_size,
+            self.term_char,
+            &mut buf,
+          ),
+          MessageKind::VendorSpecific => {
+            RequestVendorSpecificInHeader::encode_message(self.b_tag, self.transfer_size, &mut buf)
+          }
+        }
+
+        self.state = ReadState::Response;
+        Ok(Step::Action(Action::WriteBulk(buf)))
+      }
+    }
+  }
+}
+
+impl ReadTransfer {
+  /// Throw away up to `count` transfers whose bTag doesn't match the
+  /// request, as left over from an earlier session, and keep reading
+  /// without asking again.  Others are still reported as
+  /// [ClassError::MismatchedTag] unless the tolerance accepts them.
+  pub fn skip_stale_replies(mut self, count: usize) -> Self {
+    self.stale_replies = count;
+    self
+  }
+
+  /// Decode the received transfer into its bTag, data and EOM bit.
+  /// Vendor-specific transfers never have the EOM bit set.
+  fn decode(&self, tolerance: &Tolerance) -> Result<(u8, Vec<u8>, bool), ClassError> {
+    match self.kind {
+      MessageKind::DevDep => {
+        let (header, data) = DevDepMsgInHeader::decode_transfer_with(&self.received, tolerance)?;
+        Ok((header.bulk_in_header.b_tag, data.to_vec(), header.is_eom()))
+      }
+      MessageKind::VendorSpecific => {
+        let (header, data) =
+          VendorSpecificInHeader::decode_transfer_with(&self.received, tolerance)?;
+        Ok((header.bulk_in_header.b_tag, data.to_vec(), false))
+      }
+    }
+  }
+
+  fn read_more<T>(&mut self, engine: &Engine, length: usize) -> Step<T> {
+    self.requested = engine.bulk_in_read_size(length);
+    Step::Action(Action::ReadBulk(self.requested))
+  }
+}
+
+/// See [Engine::read_message]
+#[derive(Debug, Clone)]
+pub struct ReadMessage {
+  transfer: ReadTransfer,
+  data: Vec<u8>,
+}
+
+impl ReadMessage {
+  /// See [ReadTransfer::skip_stale_replies]; the count is shared by all the
+  /// transfers of the message
+  pub fn skip_stale_replies(mut self, count: usize) -> Self {
+    self.transfer = self.transfer.skip_stale_replies(count);
+    self
+  }
+}
+
+impl Operation for ReadMessage {
+  type Output = Vec<u8>;
+
+  fn step(
+    &mut self,
+    engine: &mut Engine,
+    completion: Completion,
+  ) -> Result<Step<Vec<u8>>, ClassError> {
+    match self.transfer.step(engine, completion)? {
+      Step::Action(action) => Ok(Step::Action(action)),
+      Step::Done((data, eom)) => {
+        self.data.extend_from_slice(&data);
+
+        if eom {
+          Ok(Step::Done(std::mem::take(&mut self.data)))
+        } else {
+          // start the next transfer
+          self.step(engine, Completion::Start)
+        }
+      }
+    }
+  }
+}
+
+/// See [Engine::read_vendor_specific]
+#[derive(Debug, Clone)]
+pub struct ReadVendorSpecific {
+  max_len: u32,
+  max_transfer_size: u32,
+  transfer: Option<ReadTransfer>,
+  data: Vec<u8>,
+}
+
+impl Operation for ReadVendorSpecific {
+  type Output = Vec<u8>;
+
+  fn step(
+    &mut self,
+    engine: &mut Engine,
+    completion: Completion,
+  ) -> Result<Step<Vec<u8>>, ClassError> {
+    let transfer = match &mut self.transfer {
+      Some(transfer) => transfer,
+      None => return self.next_transfer(engine),
+    };
+
+    match transfer.step(engine, completion)? {
+      Step::Action(action) => Ok(Step::Action(action)),
+      Step::Done((data, _)) => {
+        let short = data.len() < transfer.transfer_size as usize;
+        self.data.extend_from_slice(&data);
+
+        if short {
+          Ok(Step::Done(std::mem::take(&mut self.data)))
+        } else {
+          self.next_transfer(engine)
+        }
+      }
+    }
+  }
+}
+
+impl ReadVendorSpecific {
+  fn next_transfer(&mut self, engine: &mut Engine) -> Result<Step<Vec<u8>>, ClassError> {
+    if self.max_transfer_size == 0 {
+      return Err(ClassError::InvalidTransferSize);
+    }
+
+    let remaining = self.max_len.saturating_sub(self.data.len() as u32);
+    if remaining == 0 {
+      return Ok(Step::Done(std::mem::take(&mut self.data)));
+    }
+
+    let transfer_size = remaining.min(self.max_transfer_size);
+    self.transfer = Some(engine.read_vendor_specific_transfer(transfer_size));
+    self.step(engine, Completion::Start)
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum ClearState {
+  Initiate,
+  CheckStatus,
+  Poll,
+  Drain,
+  ClearHalt,
+}
+
+impl Clear {
+  fn drain(&mut self, engine: &Engine) -> Step<()> {
+    self.requested = engine.bulk_in_read_size(4096);
+    Step::Action(Action::DrainBulk(self.requested))
+  }
+}
+
+/// See [Engine::clear]
+#[derive(Debug, Clone)]
+pub struct Clear {
+  state: ClearState,
+  requested: usize,
+}
+
+impl Operation for Clear {
+  type Output = ();
+
+  fn step(&mut self, engine: &mut Engine, completion: Completion) -> Result<Step<()>, ClassError> {
+    match (self.state, completion) {
+      (ClearState::Initiate, _) => {
+        self.state = ClearState::CheckStatus;
+        Ok(Step::Action(engine.interface_request(
+          ControlRequest::InitiateClear,
+          0,
+          1,
+        )))
+      }
+      (ClearState::CheckStatus, Completion::Read(out)) => {
+        ControlRequest::check_response_status(out)?;
+
+        // device accepted `clear` command, wait while status is "pending"
+        self.state = ClearState::Poll;
+        Ok(Step::Action(engine.interface_request(
+          ControlRequest::CheckClearStatus,
+          0,
+          2,
+        )))
+      }
+      (ClearState::Poll, Completion::Read(out)) => {
+        match ControlRequest::read_response_status(out)? {
+          Status::Success => {
+            self.state = ClearState::ClearHalt;
+            Ok(Step::Action(Action::ClearHalt(
+              engine.endpoints.bulk_out_address,
+            )))
+          }
+          // bmClear D0: the device is waiting for us to read out whatever
+          // is left in its bulk-in FIFO before it can finish clearing
+          Status::Pending if out.len() >= 2 && out[1] & 0x01 != 0 => {
+            self.state = ClearState::Drain;
+            Ok(self.drain(engine))
+          }
+          Status::Pending => Ok(Step::Action(Action::Sleep(Duration::from_millis(100)))),
+          status => Err(ClassError::UnexpectedStatus(status)),
+        }
+      }
+      (ClearState::Drain, Completion::Read(buf)) if buf.len() >= self.requested => {
+        Ok(self.drain(engine))
+      }
+      (ClearState::Poll, _) | (ClearState::Drain, _) => {
+        self.state = ClearState::Poll;
+        Ok(Step::Action(engine.interface_request(
+          ControlRequest::CheckClearStatus,
+          0,
+          2,
+        )))
+      }
+      (ClearState::ClearHalt, _) => Ok(Step::Done(())),
+      (ClearState::CheckStatus, _) => Err(ClassError::TruncatedControlResponse),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use byteorder::{ByteOrder, LittleEndian};
+
+  fn engine() -> Engine {
+    Engine::new(TMCInterface::for_tests())
+  }
+
+  fn response(b_tag: u8, data: &[u8], eom: bool) -> Vec<u8> {
+    let mut buf = Vec::new();
+    DevDepMsgInHeader::encode_message(b_tag, data, eom, &mut buf);
+    buf
+  }
+
+  fn expect_write<T: std::fmt::Debug>(step: Step<T>) -> Vec<u8> {
+    match step {
+      Step::Action(Action::WriteBulk(buf)) => buf,
+      step => panic!("expected a bulk-out write, got {:?}", step),
+    }
+  }
+
+  fn expect_action<T: std::fmt::Debug>(step: Step<T>) -> Action {
+    match step {
+      Step::Action(action) => action,
+      step => panic!("expected an action, got {:?}", step),
+    }
+  }
+
+  fn control_request(step: Step<()>) -> (u8, usize) {
+    match expect_action(step) {
+      Action::ReadControl {
+        request, length, ..
+      } => (request, length),
+      action => panic!("expected a control request, got {:?}", action),
+    }
+  }
+
+  /// Send a REQUEST_DEV_DEP_MSG_IN and return its bTag
+  fn request_transfer<O: Operation>(
+    op: &mut O,
+    engine: &mut Engine,
+    completion: Completion,
+    transfer_size: u32,
+  ) -> u8
+  where
+    O::Output: std::fmt::Debug,
+  {
+    let request = expect_write(op.step(engine, completion).unwrap());
+    assert_eq!(request.len(), HEADER_SIZE);
+    assert_eq!(request[0], u8::from(MsgIdOut::RequestDevDepMsgIn));
+    assert_eq!(request[1], engine.b_tag());
+    assert_eq!(LittleEndian::read_u32(&request[4..8]), transfer_size);
+    request[1]
+  }
+
+  #[test]
+  fn write_message_sets_eom_on_last_transfer() {
+    let mut engine = engine();
+    let mut op = engine.write_message(b"hello", 3);
+
+    let first = expect_write(op.step(&mut engine, Completion::Start).unwrap());
+    assert_eq!(first[8], 0);
+    let second = expect_write(
+      op.step(&mut engine, Completion::Written(first.len()))
+        .unwrap(),
+    );
+    assert_eq!(second[8], 1);
+    assert_ne!(first[1], second[1]);
+    assert_eq!(
+      op.step(&mut engine, Completion::Written(second.len()))
+        .unwrap(),
+      Step::Done(())
+    );
+
+    // a message part never ends the message
+    let mut op = engine.write_message_part(b"hello", 3, false);
+    let first = expect_write(op.step(&mut engine, Completion::Start).unwrap());
+    let second = expect_write(
+      op.step(&mut engine, Completion::Written(first.len()))
+        .unwrap(),
+    );
+    assert_eq!((first[8], second[8]), (0, 0));
+  }
+
+  #[test]
+  fn write_message_splits_into_three_transfers() {
+    let mut engine = engine();
+    let mut op = engine.write_message(b"0123456789", 4);
+
+    let mut frames = Vec::new();
+    let mut step = op.step(&mut engine, Completion::Start).unwrap();
+    while let Step::Action(Action::WriteBulk(frame)) = step {
+      step = op
+        .step(&mut engine, Completion::Written(frame.len()))
+        .unwrap();
+      frames.push(frame);
+    }
+    assert_eq!(step, Step::Done(()));
+
+    let expected: [(&[u8], u8); 3] = [(b"0123", 0), (b"4567", 0), (b"89", 1)];
+    assert_eq!(frames.len(), expected.len());
+    for (frame, (payload, eom)) in frames.iter().zip(expected.iter()) {
+      assert_eq!(frame[0], u8::from(MsgIdOut::DevDepMsgOut));
+      assert_eq!(frame[2], !frame[1]);
+      assert_eq!(LittleEndian::read_u32(&frame[4..8]) as usize, payload.len());
+      assert_eq!(frame[8], *eom);
+      assert_eq!(&frame[HEADER_SIZE..HEADER_SIZE + payload.len()], *payload);
+
+      // padded with zeros to a multiple of 4
+      assert_eq!(frame.len() % 4, 0);
+      assert!(frame[HEADER_SIZE + payload.len()..].iter().all(|&b| b == 0));
+    }
+    assert_eq!(frames[2].len(), HEADER_SIZE + 4);
+
+    // every frame needs a different bTag
+    assert_ne!(frames[0][1], frames[1][1]);
+    assert_ne!(frames[1][1], frames[2][1]);
+  }
+
+  #[test]
+  fn write_message_rejects_truncated_transfer() {
+    let mut engine = engine();
+    let mut op = engine.write_message(b"0123456789", 4);
+
+    let first = expect_write(op.step(&mut engine, Completion::Start).unwrap());
+    let second = expect_write(
+      op.step(&mut engine, Completion::Written(first.len()))
+        .unwrap(),
+    );
+
+    // losing the padding counts too
+    assert_eq!(
+      op.step(&mut engine, Completion::Written(second.len() - 1)),
+      Err(ClassError::TruncatedBulkOut)
+    );
+  }
+
+  #[test]
+  fn read_message_joins_transfers_until_eom() {
+    let mut engine = engine();
+    let mut op = engine.read_message(64, None);
+
+    let b_tag = request_transfer(&mut op, &mut engine, Completion::Start, 64);
+    let step = op
+      .step(&mut engine, Completion::Written(HEADER_SIZE))
+      .unwrap();
+    // header, data and padding, rounded up to whole packets
+    assert_eq!(expect_action(step), Action::ReadBulk(128));
+
+    let piece = response(b_tag, b"abc", false);
+    let b_tag = request_transfer(&mut op, &mut engine, Completion::Read(&piece), 64);
+    op.step(&mut engine, Completion::Written(HEADER_SIZE))
+      .unwrap();
+
+    let piece = response(b_tag, b"def", true);
+    assert_eq!(
+      op.step(&mut engine, Completion::Read(&piece)).unwrap(),
+      Step::Done(b"abcdef".to_vec())
+    );
+  }
+
+  #[test]
+  fn read_transfer_with_header_and_data_in_separate_reads() {
+    let mut engine = engine();
+    let mut op = engine.read_transfer(64, None);
+
+    let b_tag = request_transfer(&mut op, &mut engine, Completion::Start, 64);
+    op.step(&mut engine, Completion::Written(HEADER_SIZE))
+      .unwrap();
+
+    let transfer = response(b_tag, b"hello", true);
+    let (header, data) = transfer.split_at(HEADER_SIZE);
+
+    // the header alone is a short packet, but the data it announced is still to come
+    let step = op.step(&mut engine, Completion::Read(header)).unwrap();
+    assert_eq!(expect_action(step), Action::ReadBulk(64));
+
+    assert_eq!(
+      op.step(&mut engine, Completion::Read(data)).unwrap(),
+      Step::Done((b"hello".to_vec(), true))
+    );
+  }
+
+  #[test]
+  fn read_transfer_rejects_mismatched_tag() {
+    let mut engine = engine();
+    let mut op = engine.read_transfer(64, None);
+
+    let b_tag = request_transfer(&mut op, &mut engine, Completion::Start, 64);
+    op.step(&mut engine, Completion::Written(HEADER_SIZE))
+      .unwrap();
+
+    let stale = response(b_tag.wrapping_add(7), b"old", true);
+    assert_eq!(
+      op.step(&mut engine, Completion::Read(&stale)),
+      Err(ClassError::MismatchedTag)
+    );
+
+    // unless told to put up with it
+    engine.set_tolerance(Tolerance {
+      mismatched_tag: true,
+      ..Tolerance::default()
+    });
+    let mut op = engine.read_transfer(64, None);
+    let b_tag = request_transfer(&mut op, &mut engine, Completion::Start, 64);
+    op.step(&mut engine, Completion::Written(HEADER_SIZE))
+      .unwrap();
+
+    let stale = response(b_tag.wrapping_add(7), b"old", true);
+    assert_eq!(
+      op.step(&mut engine, Completion::Read(&stale)).unwrap(),
+      Step::Done((b"old".to_vec(), true))
+    );
+  }
+
+  #[test]
+  fn tolerated_oversize_header_cannot_grow_the_read() {
+    let mut engine = engine();
+    engine.set_tolerance(Tolerance {
+      oversize_transfer: true,
+      ..Tolerance::default()
+    });
+    let mut op = engine.read_transfer(64, None);
+
+    let b_tag = request_transfer(&mut op, &mut engine, Completion::Start, 64);
+    op.step(&mut engine, Completion::Written(HEADER_SIZE))
+      .unwrap();
+
+    // as much as was asked for, claiming far more is to come
+    let mut transfer = response(b_tag, &[0x55; 116], true);
+    LittleEndian::write_u32(&mut transfer[4..8], 0xFFFF_0000);
+    assert_eq!(transfer.len(), 128);
+
+    // the transfer can't be longer than the request allowed for
+    assert_eq!(
+      op.step(&mut engine, Completion::Read(&transfer)).unwrap(),
+      Step::Done((vec![0x55; 116], true))
+    );
+  }
+
+  fn vendor_specific_response(b_tag: u8, data: &[u8]) -> Vec<u8> {
+    let mut buf = response(b_tag, data, false);
+    buf[0] = MsgIdIn::VendorSpecificIn.into();
+    buf
+  }
+
+  #[test]
+  fn write_vendor_specific_splits_into_transfers() {
+    let mut engine = engine();
+    let mut op = engine.write_vendor_specific(b"0123456789", 8);
+
+    let first = expect_write(op.step(&mut engine, Completion::Start).unwrap());
+    assert_eq!(first[0], u8::from(MsgIdOut::VendorSpecificOut));
+    assert_eq!(&first[HEADER_SIZE..], b"01234567");
+
+    let second = expect_write(
+      op.step(&mut engine, Completion::Written(first.len()))
+        .unwrap(),
+    );
+    assert_eq!(second[0], u8::from(MsgIdOut::VendorSpecificOut));
+    assert_eq!(LittleEndian::read_u32(&second[4..8]), 2);
+    assert_eq!(&second[HEADER_SIZE..], b"89\0\0");
+    assert_ne!(first[1], second[1]);
+
+    assert_eq!(
+      op.step(&mut engine, Completion::Written(second.len() - 2)),
+      Err(ClassError::TruncatedBulkOut)
+    );
+  }
+
+  #[test]
+  fn read_vendor_specific_until_short_transfer() {
+    let mut engine = engine();
+    let mut op = engine.read_vendor_specific(100, 64);
+
+    let request = expect_write(op.step(&mut engine, Completion::Start).unwrap());
+    assert_eq!(request[0], u8::from(MsgIdOut::RequestVendorSpecificIn));
+    assert_eq!(LittleEndian::read_u32(&request[4..8]), 64);
+    let step = op
+      .step(&mut engine, Completion::Written(HEADER_SIZE))
+      .unwrap();
+    assert_eq!(expect_action(step), Action::ReadBulk(128));
+
+    // all that was asked for, so there may be more
+    let full = vendor_specific_response(request[1], &[0xAA; 64]);
+    let request = expect_write(op.step(&mut engine, Completion::Read(&full)).unwrap());
+    assert_eq!(LittleEndian::read_u32(&request[4..8]), 36);
+    op.step(&mut engine, Completion::Written(HEADER_SIZE))
+      .unwrap();
+
+    // header and data may come separately
+    let last = vendor_specific_response(request[1], &[0xBB; 10]);
+    let step = op
+      .step(&mut engine, Completion::Read(&last[..HEADER_SIZE]))
+      .unwrap();
+    assert_eq!(expect_action(step), Action::ReadBulk(64));
+
+    let mut expected = vec![0xAA; 64];
+    expected.extend_from_slice(&[0xBB; 10]);
+    assert_eq!(
+      op.step(&mut engine, Completion::Read(&last[HEADER_SIZE..]))
+        .unwrap(),
+      Step::Done(expected)
+    );
+  }
+
+  #[test]
+  fn read_vendor_specific_rejects_dev_dep_transfer() {
+    let mut engine = engine();
+    let mut op = engine.read_vendor_specific(16, 64);
+
+    let request = expect_write(op.step(&mut engine, Completion::Start).unwrap());
+    op.step(&mut engine, Completion::Written(HEADER_SIZE))
+      .unwrap();
+
+    let wrong = response(request[1], b"data", true);
+    assert_eq!(
+      op.step(&mut engine, Completion::Read(&wrong)),
+      Err(ClassError::UnexpectedMsgId)
+    );
+  }
+
+  #[test]
+  fn clear_drains_bulk_in_when_asked() {
+    let mut engine = engine();
+    let mut op = engine.clear();
+
+    let step = op.step(&mut engine, Completion::Start).unwrap();
+    assert_eq!(
+      control_request(step),
+      (ControlRequest::InitiateClear.into(), 1)
+    );
+
+    let step = op.step(&mut engine, Completion::Read(&[0x01])).unwrap();
+    assert_eq!(
+      control_request(step),
+      (ControlRequest::CheckClearStatus.into(), 2)
+    );
+
+    // pending, with bmClear D0 set: read out the bulk-in FIFO
+    let step = op
+      .step(&mut engine, Completion::Read(&[0x02, 0x01]))
+      .unwrap();
+    assert_eq!(expect_action(step), Action::DrainBulk(4096));
+
+    // a full read may have left more behind
+    let step = op
+      .step(&mut engine, Completion::Read(&[0xAA; 4096]))
+      .unwrap();
+    assert_eq!(expect_action(step), Action::DrainBulk(4096));
+
+    // an empty read (or a timeout) means it's empty, so check again
+    let step = op.step(&mut engine, Completion::Read(&[])).unwrap();
+    assert_eq!(
+      control_request(step),
+      (ControlRequest::CheckClearStatus.into(), 2)
+    );
+
+    let step = op
+      .step(&mut engine, Completion::Read(&[0x01, 0x00]))
+      .unwrap();
+    assert_eq!(expect_action(step), Action::ClearHalt(0x01));
+    assert_eq!(
+      op.step(&mut engine, Completion::Done).unwrap(),
+      Step::Done(())
+    );
+  }
+}
@@ -0,0 +1,261 @@
+use crate::class::*;
+use crate::instrument::list_instruments_where;
+use crate::{list_instruments, Instrument, InstrumentId, OpenOptions, TMCResult};
+
+type Usb488Filter = Box<dyn Fn(&USB488Capabilities) -> bool>;
+
+/// Find every connected instrument matching a set of criteria.
+///
+/// Criteria that can be checked from descriptors are tried first.  Checking
+/// USB488 capabilities or the SCPI ID means opening the instrument, though
+/// without sending CLEAR, so those are only checked on instruments that
+/// pass everything else.
+///
+/// ```no_run
+/// # fn main() -> tmc::TMCResult<()> {
+/// # let context = rusb::Context::new()?;
+/// let sensors = tmc::InstrumentQuery::new()
+///   .vendor_id(0x0957)
+///   .scpi_id("Agilent Technologies,U2001A,*")
+///   .find(context)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct InstrumentQuery {
+  vendor_id: Option<u16>,
+  product_id: Option<u16>,
+  serial_number: Option<String>,
+  manufacturer: Option<String>,
+  product: Option<String>,
+  port_path: Option<(u8, Vec<u8>)>,
+  usb488: Option<Usb488Filter>,
+  scpi_id: Option<String>,
+}
+
+impl InstrumentQuery {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn vendor_id(&mut self, vendor_id: u16) -> &mut Self {
+    self.vendor_id = Some(vendor_id);
+    self
+  }
+
+  pub fn product_id(&mut self, product_id: u16) -> &mut Self {
+    self.product_id = Some(product_id);
+    self
+  }
+
+  pub fn serial_number(&mut self, serial_number: &str) -> &mut Self {
+    self.serial_number = Some(serial_number.to_owned());
+    self
+  }
+
+  /// Match the manufacturer string descriptor exactly
+  pub fn manufacturer(&mut self, manufacturer: &str) -> &mut Self {
+    self.manufacturer = Some(manufacturer.to_owned());
+    self
+  }
+
+  /// Match the product string descriptor exactly
+  pub fn product(&mut self, product: &str) -> &mut Self {
+    self.product = Some(product.to_owned());
+    self
+  }
+
+  /// Match the bus number and the chain of hub port numbers leading to the
+  /// device, which stay the same as long as it's plugged into the same socket
+  pub fn port_path(&mut self, bus_number: u8, port_numbers: &[u8]) -> &mut Self {
+    self.port_path = Some((bus_number, port_numbers.to_vec()));
+    self
+  }
+
+  /// Match USB488 instruments whose capabilities satisfy `filter`
+  pub fn usb488<F>(&mut self, filter: F) -> &mut Self
+  where
+    F: Fn(&USB488Capabilities) -> bool + 'static,
+  {
+    self.usb488 = Some(Box::new(filter));
+    self
+  }
+
+  /// Match the response to `*IDN?` against a pattern, in which `*` matches
+  /// any run of characters and `?` any single character.  Letters match
+  /// regardless of case.  Only SCPI instruments can match.
+  pub fn scpi_id(&mut self, pattern: &str) -> &mut Self {
+    self.scpi_id = Some(pattern.to_owned());
+    self
+  }
+
+  /// List all the connected instruments matching the query
+  pub fn find<Ctx: rusb::UsbContext>(&self, context: Ctx) -> TMCResult<Vec<Instrument<Ctx>>> {
+    let mut matched = Vec::new();
+    let mut probed: Vec<InstrumentId> = Vec::new();
+
+    for mut instrument in list_instruments(context.clone())? {
+      if !self.matches_descriptors(&mut instrument) {
+        continue;
+      }
+
+      if self.usb488.is_none() && self.scpi_id.is_none() {
+        matched.push(instrument);
+      } else {
+        let id = instrument.id();
+        if self.matches_session(instrument) {
+          probed.push(id);
+        }
+      }
+    }
+
+    // Opening an instrument to check it uses it up, so find the ones that
+    // passed again.
+    if !probed.is_empty() {
+      let found = list_instruments_where(context, |device| {
+        probed
+          .iter()
+          .any(|id| id.bus_number == device.bus_number() && id.address == device.address())
+      })?;
+
+      matched.extend(
+        found
+          .into_iter()
+          .filter(|instrument| probed.contains(&instrument.id())),
+      );
+    }
+
+    Ok(matched)
+  }
+
+  /// Check the criteria which only need descriptors
+  fn matches_descriptors<Ctx: rusb::UsbContext>(&self, instrument: &mut Instrument<Ctx>) -> bool {
+    let device_desc = &instrument.device_desc;
+
+    if matches!(self.vendor_id, Some(id) if id != device_desc.vendor_id())
+      || matches!(self.product_id, Some(id) if id != device_desc.product_id())
+    {
+      return false;
+    }
+
+    // only USB488 interfaces have USB488 capabilities
+    if self.usb488.is_some() && instrument.endpoints.interface_protocol != 1 {
+      return false;
+    }
+
+    if let Some((bus_number, port_numbers)) = &self.port_path {
+      if *bus_number != instrument.device.bus_number()
+        || instrument.device.port_numbers().ok().as_ref() != Some(port_numbers)
+      {
+        return false;
+      }
+    }
+
+    matches_string(&self.manufacturer, || instrument.read_manufacturer())
+      && matches_string(&self.product, || instrument.read_product())
+      && matches_string(&self.serial_number, || instrument.read_serial_number())
+  }
+
+  /// Check the criteria which need a session with the instrument
+  fn matches_session<Ctx: rusb::UsbContext>(&self, instrument: Instrument<Ctx>) -> bool {
+    let handle = match OpenOptions::new()
+      .clear(false)
+      .query_id(self.scpi_id.is_some())
+      .open(instrument)
+    {
+      Ok(handle) => handle,
+      Err(_) => return false,
+    };
+
+    if let Some(filter) = &self.usb488 {
+      match &handle.usb488_capabilities {
+        Some(caps) if filter(caps) => {}
+        _ => return false,
+      }
+    }
+
+    if let Some(pattern) = &self.scpi_id {
+      match &handle.scpi_id {
+        Some(scpi_id) if glob_match(pattern, scpi_id) => {}
+        _ => return false,
+      }
+    }
+
+    true
+  }
+}
+
+/// Check an optional criterion against a string that may have to be read
+/// from the device.  A string that can't be read doesn't match.
+fn matches_string<F>(wanted: &Option<String>, read: F) -> bool
+where
+  F: FnOnce() -> TMCResult<Option<String>>,
+{
+  match wanted {
+    None => true,
+    Some(wanted) => matches!(read(), Ok(Some(actual)) if actual == *wanted),
+  }
+}
+
+/// Match text against a pattern with `*` and `?` wildcards, ignoring ASCII
+/// case.  `?` matches a single character, however many bytes it takes.
+fn glob_match(pattern: &str, text: &str) -> bool {
+  let pattern: Vec<char> = pattern.chars().collect();
+  let text: Vec<char> = text.chars().collect();
+  let (mut p, mut t) = (0, 0);
+  // where to resume if the characters after the last `*` stop matching
+  let mut backtrack: Option<(usize, usize)> = None;
+
+  while t < text.len() {
+    match pattern.get(p) {
+      Some('*') => {
+        backtrack = Some((p + 1, t));
+        p += 1;
+      }
+      Some(&expected) if expected == '?' || expected.eq_ignore_ascii_case(&text[t]) => {
+        p += 1;
+        t += 1;
+      }
+      _ => match backtrack {
+        Some((star_p, star_t)) => {
+          // let the `*` swallow one more character
+          backtrack = Some((star_p, star_t + 1));
+          p = star_p;
+          t = star_t + 1;
+        }
+        None => return false,
+      },
+    }
+  }
+
+  pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+  use super::glob_match;
+
+  #[test]
+  fn wildcards() {
+    assert!(glob_match(
+      "Agilent Technologies,U2001A,*",
+      "AGILENT TECHNOLOGIES,U2001A,MY1234,A1.01"
+    ));
+    assert!(glob_match(
+      "*,U200?A,*",
+      "Agilent Technologies,U2002A,MY1234,A1.01"
+    ));
+    assert!(!glob_match(
+      "*,U200?A,*",
+      "Agilent Technologies,U2002AB,MY1234,A1.01"
+    ));
+    assert!(glob_match("*", ""));
+    assert!(!glob_match("?", ""));
+  }
+
+  #[test]
+  fn question_mark_matches_one_character() {
+    assert!(glob_match("Gr??e", "Grüße"));
+    assert!(!glob_match("Gr???e", "Grüße"));
+  }
+}
@@ -0,0 +1,66 @@
+use crate::class::*;
+use core::time::Duration;
+use std::sync::RwLock;
+
+/// Known deviations from the spec for a particular model of instrument.
+///
+/// [InstrumentHandle::connect](crate::InstrumentHandle::connect) looks up the
+/// quirks for the vendor and product ID its transport reports with
+/// [Quirks::for_device], and the handle works around them from then on.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Quirks {
+  /// Deviations to accept when decoding bulk-in transfers, such as a wrong
+  /// `transfer_size` in response headers
+  pub tolerance: Tolerance,
+
+  /// The instrument claims to support a TermChar but doesn't honour it, so
+  /// one is never sent.
+  pub no_term_char: bool,
+
+  /// Minimum time between consecutive bulk-out transfers
+  pub command_delay: Duration,
+
+  /// CLEAR confuses the instrument, so it isn't sent when connecting.
+  pub broken_clear: bool,
+
+  /// The largest transfer the instrument can really handle, if smaller than
+  /// the handle's `max_transfer_size`
+  pub max_transfer_size: Option<u32>,
+}
+
+/// Quirks of instruments known to this crate, by vendor and product ID.
+/// Entries should say where the misbehaviour was documented or observed.
+const BUILTIN_QUIRKS: &[(u16, u16, Quirks)] = &[];
+
+/// Quirks added by the application, which take precedence over the builtin ones
+static REGISTERED_QUIRKS: RwLock<Vec<(u16, u16, Quirks)>> = RwLock::new(Vec::new());
+
+impl Quirks {
+  /// Look up the quirks for an instrument.  Instruments that aren't known to
+  /// misbehave get the default, which works around nothing.
+  pub fn for_device(vendor_id: u16, product_id: u16) -> Self {
+    let matches = |&&(vid, pid, _): &&(u16, u16, Quirks)| vid == vendor_id && pid == product_id;
+
+    let registered = REGISTERED_QUIRKS
+      .read()
+      .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    registered
+      .iter()
+      .find(matches)
+      .or_else(|| BUILTIN_QUIRKS.iter().find(matches))
+      .map(|&(_, _, quirks)| quirks)
+      .unwrap_or_default()
+  }
+
+  /// Add or replace the quirks for an instrument, for all handles opened
+  /// from now on.
+  pub fn register(vendor_id: u16, product_id: u16, quirks: Quirks) {
+    let mut registered = REGISTERED_QUIRKS
+      .write()
+      .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    registered.retain(|&(vid, pid, _)| vid != vendor_id || pid != product_id);
+    registered.push((vendor_id, product_id, quirks));
+  }
+}
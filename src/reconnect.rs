@@ -0,0 +1,206 @@
+use crate::{
+  list_instruments, Instrument, InstrumentHandle, OpenOptions, ResourceString, TMCError, TMCResult,
+  UsbTransport,
+};
+use core::time::Duration;
+use std::thread::sleep;
+use std::time::Instant;
+
+/// How often to look for the instrument while waiting for it to come back
+const RECONNECT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// An [InstrumentHandle] which connects again when the instrument goes away
+/// and comes back, for example because it rebooted.
+///
+/// The instrument is remembered by vendor ID, product ID, serial number and
+/// interface number, as in its resource string.  An instrument without a
+/// serial number can't be told apart from another of the same model that
+/// way, so it's also remembered by the bus and hub ports it's plugged into,
+/// and is only reconnected if it comes back on the same port.
+///
+/// When a request fails with [rusb::Error::NoDevice], the handle waits for
+/// a matching instrument to appear and connects to it with the term char,
+/// timeout and max transfer size the old connection had.  The failed
+/// request then returns [TMCError::Reconnected]; it isn't retried, since
+/// the instrument may have lost its state.
+pub struct ReconnectingHandle<Ctx: rusb::UsbContext> {
+  context: Ctx,
+  resource: ResourceString,
+  /// The bus number and port numbers, for an instrument without a serial number
+  port_path: Option<(u8, Vec<u8>)>,
+  options: OpenOptions,
+  reconnect_timeout: Duration,
+  reconnects: u64,
+
+  handle: Option<InstrumentHandle<UsbTransport<Ctx>>>,
+}
+
+impl<Ctx: rusb::UsbContext> ReconnectingHandle<Ctx> {
+  /// Open an instrument, remembering how to find it again
+  pub fn open(
+    context: Ctx,
+    mut instrument: Instrument<Ctx>,
+    options: &OpenOptions,
+  ) -> TMCResult<Self> {
+    let mut resource = instrument.resource_string()?;
+    resource.interface_number = Some(instrument.endpoints.interface_number);
+
+    let port_path = match resource.serial_number {
+      Some(_) => None,
+      None => Some((
+        instrument.device.bus_number(),
+        instrument.device.port_numbers()?,
+      )),
+    };
+
+    let handle = options.open(instrument)?;
+
+    Ok(Self {
+      context,
+      resource,
+      port_path,
+      options: options.clone(),
+      reconnect_timeout: Duration::from_secs(10),
+      reconnects: 0,
+
+      handle: Some(handle),
+    })
+  }
+
+  /// The resource string identifying the instrument to reconnect to
+  pub fn resource(&self) -> &ResourceString {
+    &self.resource
+  }
+
+  pub fn get_reconnect_timeout(&self) -> Duration {
+    self.reconnect_timeout
+  }
+
+  /// Set how long to wait for the instrument to come back after it goes away
+  pub fn set_reconnect_timeout(&mut self, reconnect_timeout: Duration) {
+    self.reconnect_timeout = reconnect_timeout;
+  }
+
+  /// How many times the instrument has been reconnected
+  pub fn reconnects(&self) -> u64 {
+    self.reconnects
+  }
+
+  /// Whether there is currently a connection to the instrument
+  pub fn is_connected(&self) -> bool {
+    self.handle.is_some()
+  }
+
+  /// Get the current connection, waiting for the instrument to come back if
+  /// it has gone away
+  pub fn handle(&mut self) -> TMCResult<&mut InstrumentHandle<UsbTransport<Ctx>>> {
+    if self.handle.is_none() {
+      self.reconnect()?;
+    }
+
+    Ok(self.handle.as_mut().expect("just reconnected"))
+  }
+
+  /// Run a function on the current connection, reconnecting if it finds the
+  /// instrument gone.
+  pub fn with_handle<R, F>(&mut self, f: F) -> TMCResult<R>
+  where
+    F: FnOnce(&mut InstrumentHandle<UsbTransport<Ctx>>) -> TMCResult<R>,
+  {
+    match f(self.handle()?) {
+      Err(TMCError::Rusb(rusb::Error::NoDevice)) => {
+        self.disconnect();
+        self.reconnect()?;
+        Err(TMCError::Reconnected)
+      }
+      result => result,
+    }
+  }
+
+  /// Drop the connection, keeping its settings for the next one
+  fn disconnect(&mut self) {
+    if let Some(handle) = self.handle.take() {
+      self
+        .options
+        .term_char(handle.get_term_char())
+        .timeout(handle.get_timeout())
+        .max_transfer_size(handle.get_max_transfer_size());
+    }
+  }
+
+  /// Wait for the instrument to appear and connect to it
+  fn reconnect(&mut self) -> TMCResult<()> {
+    let deadline = Instant::now() + self.reconnect_timeout;
+
+    loop {
+      if let Some(instrument) = self.find_instrument() {
+        // it may be visible before it's ready to talk
+        if let Ok(handle) = self.options.open(instrument) {
+          self.handle = Some(handle);
+          self.reconnects += 1;
+          return Ok(());
+        }
+      }
+
+      if Instant::now() >= deadline {
+        return Err(rusb::Error::NoDevice.into());
+      }
+
+      sleep(RECONNECT_POLL_INTERVAL);
+    }
+  }
+
+  /// Look for the instrument among those connected.  The bus may be in flux
+  /// while it comes back, so an error looking for it just means it isn't
+  /// back yet.
+  fn find_instrument(&self) -> Option<Instrument<Ctx>> {
+    let instruments = list_instruments(self.context.clone()).ok()?;
+
+    instruments.into_iter().find_map(|mut instrument| {
+      if !self.resource.matches(&mut instrument).unwrap_or(false) {
+        return None;
+      }
+
+      if let Some((bus_number, port_numbers)) = &self.port_path {
+        let device = &instrument.device;
+        if device.bus_number() != *bus_number
+          || device.port_numbers().ok().as_ref() != Some(port_numbers)
+        {
+          return None;
+        }
+      }
+
+      Some(instrument)
+    })
+  }
+
+  /// Write a command message to the instrument
+  pub fn write_raw(&mut self, data: &[u8]) -> TMCResult<()> {
+    self.with_handle(|handle| handle.write_raw(data))
+  }
+
+  /// Read response data from the instrument
+  pub fn read_raw(&mut self, transfer_size: Option<u32>) -> TMCResult<Vec<u8>> {
+    self.with_handle(|handle| handle.read_raw(transfer_size))
+  }
+
+  /// Read UTF-8 response data from the instrument
+  pub fn read(&mut self, transfer_size: Option<u32>) -> TMCResult<String> {
+    self.with_handle(|handle| handle.read(transfer_size))
+  }
+
+  /// Write a UTF-8 command message to the instrument
+  pub fn write(&mut self, message: &str) -> TMCResult<()> {
+    self.with_handle(|handle| handle.write(message))
+  }
+
+  /// Write a UTF-8 command message to the instrument and read a UTF-8 response
+  pub fn ask(&mut self, data: &str) -> TMCResult<String> {
+    self.with_handle(|handle| handle.ask(data))
+  }
+
+  /// Write a command message to the instrument and read a response
+  pub fn ask_raw(&mut self, data: &[u8]) -> TMCResult<Vec<u8>> {
+    self.with_handle(|handle| handle.ask_raw(data))
+  }
+}
@@ -0,0 +1,289 @@
+use crate::{
+  list_instruments, Instrument, InstrumentHandle, OpenOptions, TMCError, TMCResult, UsbTransport,
+};
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::str::FromStr;
+
+/// A VISA resource string for a USB instrument, such as
+/// `USB0::0x0957::0x2B18::MY1234::0::INSTR`.
+///
+/// The grammar is `USB[board]::vendor::product[::serial[::interface]][::INSTR]`,
+/// where numbers may be written in decimal or in hex with a `0x` prefix.
+/// Parsing is case-insensitive for the `USB` and `INSTR` keywords.  When
+/// formatted, IDs are written in hex and the board index is always included,
+/// as VISA does.  An interface number without a serial number is written
+/// with an empty serial field, as in `USB0::0x0957::0x2B18::::1::INSTR`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResourceString {
+  pub board: u16,
+  pub vendor_id: u16,
+  pub product_id: u16,
+  pub serial_number: Option<String>,
+  pub interface_number: Option<u8>,
+}
+
+impl ResourceString {
+  /// Check whether this resource string names an instrument.  This may
+  /// involve connecting to it in order to read its serial number.
+  pub fn matches<Ctx: rusb::UsbContext>(
+    &self,
+    instrument: &mut Instrument<Ctx>,
+  ) -> TMCResult<bool> {
+    if instrument.device_desc.vendor_id() != self.vendor_id
+      || instrument.device_desc.product_id() != self.product_id
+    {
+      return Ok(false);
+    }
+
+    if let Some(interface_number) = self.interface_number {
+      if instrument.endpoints.interface_number != interface_number {
+        return Ok(false);
+      }
+    }
+
+    match &self.serial_number {
+      None => Ok(true),
+      Some(serial_number) => Ok(instrument.read_serial_number()?.as_ref() == Some(serial_number)),
+    }
+  }
+}
+
+fn parse_number<N: FromStr + TryFrom<u32>>(text: &str) -> Option<N> {
+  match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+    Some(hex) => u32::from_str_radix(hex, 16).ok()?.try_into().ok(),
+    None => text.parse().ok(),
+  }
+}
+
+impl FromStr for ResourceString {
+  type Err = TMCError;
+
+  fn from_str(resource: &str) -> Result<Self, Self::Err> {
+    let invalid = || TMCError::InvalidResourceString(resource.to_owned());
+
+    let mut fields: Vec<&str> = resource.trim().split("::").collect();
+    if fields.len() > 1 && fields[fields.len() - 1].eq_ignore_ascii_case("INSTR") {
+      fields.pop();
+    }
+
+    if fields.len() < 3 || fields.len() > 5 {
+      return Err(invalid());
+    }
+
+    let board = match fields[0].get(..3) {
+      Some(interface_type) if interface_type.eq_ignore_ascii_case("USB") => &fields[0][3..],
+      _ => return Err(invalid()),
+    };
+    let board = match board {
+      "" => 0,
+      board => board.parse().map_err(|_| invalid())?,
+    };
+
+    let vendor_id = parse_number(fields[1]).ok_or_else(invalid)?;
+    let product_id = parse_number(fields[2]).ok_or_else(invalid)?;
+
+    let serial_number = match fields.get(3) {
+      None => None,
+      // only allowed as a placeholder before an interface number
+      Some(&"") if fields.len() == 5 => None,
+      Some(&"") => return Err(invalid()),
+      Some(serial_number) => Some((*serial_number).to_owned()),
+    };
+
+    let interface_number = match fields.get(4) {
+      None => None,
+      Some(interface_number) => Some(parse_number(interface_number).ok_or_else(invalid)?),
+    };
+
+    Ok(ResourceString {
+      board,
+      vendor_id,
+      product_id,
+      serial_number,
+      interface_number,
+    })
+  }
+}
+
+impl fmt::Display for ResourceString {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "USB{}::0x{:04X}::0x{:04X}",
+      self.board, self.vendor_id, self.product_id
+    )?;
+
+    match (&self.serial_number, self.interface_number) {
+      (None, None) => {}
+      (Some(serial_number), None) => write!(f, "::{}", serial_number)?,
+      (serial_number, Some(interface_number)) => write!(
+        f,
+        "::{}::{}",
+        serial_number.as_deref().unwrap_or(""),
+        interface_number
+      )?,
+    }
+
+    write!(f, "::INSTR")
+  }
+}
+
+/// Find the instrument named by a VISA resource string.  Instruments whose
+/// serial number can't be read are skipped.
+pub fn find_resource<Ctx: rusb::UsbContext>(
+  context: Ctx,
+  resource: &ResourceString,
+) -> TMCResult<Option<Instrument<Ctx>>> {
+  for mut instrument in list_instruments(context)? {
+    if resource.matches(&mut instrument).unwrap_or(false) {
+      return Ok(Some(instrument));
+    }
+  }
+
+  Ok(None)
+}
+
+/// Open the instrument named by a VISA resource string, such as
+/// `USB0::0x0957::0x2B18::MY1234::INSTR`
+pub fn open_resource<Ctx: rusb::UsbContext>(
+  context: Ctx,
+  resource: &str,
+) -> TMCResult<InstrumentHandle<UsbTransport<Ctx>>> {
+  open_resource_with(context, resource, &OpenOptions::new())
+}
+
+/// Open the instrument named by a VISA resource string, with the given options
+pub fn open_resource_with<Ctx: rusb::UsbContext>(
+  context: Ctx,
+  resource: &str,
+  options: &OpenOptions,
+) -> TMCResult<InstrumentHandle<UsbTransport<Ctx>>> {
+  let resource: ResourceString = resource.parse()?;
+  let instrument = find_resource(context, &resource)?.ok_or(rusb::Error::NotFound)?;
+
+  options.open(instrument)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn resource(
+    board: u16,
+    vendor_id: u16,
+    product_id: u16,
+    serial_number: Option<&str>,
+    interface_number: Option<u8>,
+  ) -> ResourceString {
+    ResourceString {
+      board,
+      vendor_id,
+      product_id,
+      serial_number: serial_number.map(str::to_owned),
+      interface_number,
+    }
+  }
+
+  #[test]
+  fn parses_hex_and_decimal_ids() {
+    assert_eq!(
+      "USB0::0x0957::0x2B18::INSTR"
+        .parse::<ResourceString>()
+        .unwrap(),
+      resource(0, 0x0957, 0x2B18, None, None)
+    );
+    assert_eq!(
+      "USB::2391::11032::INSTR".parse::<ResourceString>().unwrap(),
+      resource(0, 0x0957, 0x2B18, None, None)
+    );
+    assert_eq!(
+      "USB0::0X0957::0x2b18::INSTR"
+        .parse::<ResourceString>()
+        .unwrap(),
+      resource(0, 0x0957, 0x2B18, None, None)
+    );
+  }
+
+  #[test]
+  fn parses_board_serial_and_interface() {
+    assert_eq!(
+      "USB3::0x0957::0x2B18::MY1234::2::INSTR"
+        .parse::<ResourceString>()
+        .unwrap(),
+      resource(3, 0x0957, 0x2B18, Some("MY1234"), Some(2))
+    );
+    assert_eq!(
+      "USB0::0x0957::0x2B18::MY1234"
+        .parse::<ResourceString>()
+        .unwrap(),
+      resource(0, 0x0957, 0x2B18, Some("MY1234"), None)
+    );
+    assert_eq!(
+      "USB0::0x0957::0x2B18::::1::INSTR"
+        .parse::<ResourceString>()
+        .unwrap(),
+      resource(0, 0x0957, 0x2B18, None, Some(1))
+    );
+  }
+
+  #[test]
+  fn keywords_are_case_insensitive() {
+    assert_eq!(
+      "usb1::0x0957::0x2B18::my1234::instr"
+        .parse::<ResourceString>()
+        .unwrap(),
+      resource(1, 0x0957, 0x2B18, Some("my1234"), None)
+    );
+  }
+
+  #[test]
+  fn rejects_malformed_strings() {
+    for text in &[
+      "",
+      "USB0::INSTR",
+      "USB0::0x0957::INSTR",
+      "GPIB0::0x0957::0x2B18::INSTR",
+      "USBx::0x0957::0x2B18::INSTR",
+      "USB0::0x10000::0x2B18::INSTR",
+      "USB0::0xZZ::0x2B18::INSTR",
+      "USB0::0x0957::0x2B18::::INSTR",
+      "USB0::0x0957::0x2B18::MY1234::256::INSTR",
+      "USB0::0x0957::0x2B18::MY1234::0::extra::INSTR",
+    ] {
+      assert!(
+        matches!(
+          text.parse::<ResourceString>(),
+          Err(TMCError::InvalidResourceString(_))
+        ),
+        "{:?} should not parse",
+        text
+      );
+    }
+  }
+
+  #[test]
+  fn formats_as_visa_and_parses_back() {
+    for (expected, resource) in &[
+      (
+        "USB0::0x0957::0x2B18::INSTR",
+        resource(0, 0x0957, 0x2B18, None, None),
+      ),
+      (
+        "USB1::0x0957::0x2B18::MY1234::INSTR",
+        resource(1, 0x0957, 0x2B18, Some("MY1234"), None),
+      ),
+      (
+        "USB0::0x0957::0x2B18::MY1234::2::INSTR",
+        resource(0, 0x0957, 0x2B18, Some("MY1234"), Some(2)),
+      ),
+      (
+        "USB0::0x0957::0x2B18::::1::INSTR",
+        resource(0, 0x0957, 0x2B18, None, Some(1)),
+      ),
+    ] {
+      assert_eq!(resource.to_string(), *expected);
+      assert_eq!(expected.parse::<ResourceString>().unwrap(), *resource);
+    }
+  }
+}
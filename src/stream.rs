@@ -0,0 +1,165 @@
+use crate::{InstrumentHandle, Transport};
+use std::io;
+
+/// Streams a command message to an instrument through [std::io::Write].
+///
+/// Data is sent in transfers of up to the handle's max transfer size.  The
+/// message is ended (the last transfer has EOM set) by [MessageWriter::end_message]
+/// or `flush`, after which further writes start a new message.  Dropping the
+/// writer ends any message in progress, ignoring errors.
+pub struct MessageWriter<'h, T: Transport> {
+  handle: &'h mut InstrumentHandle<T>,
+  buf: Vec<u8>,
+}
+
+impl<'h, T: Transport> MessageWriter<'h, T> {
+  pub(crate) fn new(handle: &'h mut InstrumentHandle<T>) -> Self {
+    Self {
+      handle,
+      buf: Vec::new(),
+    }
+  }
+
+  /// Send any buffered data with EOM set, ending the current message.
+  pub fn end_message(&mut self) -> io::Result<()> {
+    if !self.buf.is_empty() {
+      let result = self.handle.write_message_part(&self.buf, true);
+      self.buf.clear();
+      result?;
+    }
+
+    Ok(())
+  }
+
+  /// Throw away any buffered data without sending it
+  pub(crate) fn discard(&mut self) {
+    self.buf.clear();
+  }
+}
+
+impl<'h, T: Transport> io::Write for MessageWriter<'h, T> {
+  fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+    let max_transfer_size = self.handle.get_max_transfer_size() as usize;
+
+    // Hold on to a full buffer until more data arrives, so that the last
+    // transfer of a message is never an empty one just to carry EOM.
+    if self.buf.len() >= max_transfer_size && !data.is_empty() {
+      let result = self.handle.write_message_part(&self.buf, false);
+      self.buf.clear();
+      result?;
+    }
+
+    let n = data.len().min(max_transfer_size - self.buf.len());
+    self.buf.extend_from_slice(&data[..n]);
+    Ok(n)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.end_message()
+  }
+}
+
+impl<'h, T: Transport> Drop for MessageWriter<'h, T> {
+  fn drop(&mut self) {
+    let _ = self.end_message();
+  }
+}
+
+/// Streams one response message from an instrument through [std::io::Read].
+///
+/// Each time the data from the previous transfer runs out, another transfer
+/// of up to `transfer_size` bytes is requested, until the device sets EOM.
+/// After that, reads return 0 (end of file).  If the reader is dropped
+/// before then, the rest of the message is left on the device; use
+/// [InstrumentHandle::abort_bulk_in] to discard it.
+pub struct MessageReader<'h, T: Transport> {
+  handle: &'h mut InstrumentHandle<T>,
+  transfer_size: Option<u32>,
+  buf: Vec<u8>,
+  pos: usize,
+  eom: bool,
+}
+
+impl<'h, T: Transport> MessageReader<'h, T> {
+  pub(crate) fn new(handle: &'h mut InstrumentHandle<T>, transfer_size: Option<u32>) -> Self {
+    Self {
+      handle,
+      transfer_size,
+      buf: Vec::new(),
+      pos: 0,
+      eom: false,
+    }
+  }
+
+  /// Whether the whole message has been received from the device (though
+  /// not necessarily read from this reader yet)
+  pub fn is_eom(&self) -> bool {
+    self.eom
+  }
+}
+
+impl<'h, T: Transport> io::Read for MessageReader<'h, T> {
+  fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+    while self.pos >= self.buf.len() {
+      if self.eom {
+        return Ok(0);
+      }
+
+      let (data, eom) = self.handle.read_transfer(self.transfer_size)?;
+      self.buf = data;
+      self.pos = 0;
+      self.eom = eom;
+    }
+
+    let n = out.len().min(self.buf.len() - self.pos);
+    out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+    self.pos += n;
+    Ok(n)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::class::TMCInterface;
+  use crate::transport::tests::FakeTransport;
+  use crate::InstrumentHandle;
+  use std::io::Write;
+
+  fn handle(max_transfer_size: u32) -> InstrumentHandle<FakeTransport> {
+    let mut handle =
+      InstrumentHandle::connect(FakeTransport::default(), TMCInterface::for_tests()).unwrap();
+    handle.set_max_transfer_size(max_transfer_size).unwrap();
+    handle
+  }
+
+  #[test]
+  fn message_split_into_transfers() {
+    let mut handle = handle(2);
+    let mut writer = handle.message_writer();
+    writer.write_all(b"MEAS?").unwrap();
+    writer.flush().unwrap();
+    drop(writer);
+
+    // after the `*IDN?` sent while connecting
+    let transfers = handle.transport().message_transfers();
+    assert_eq!(transfers[1..], [(2, false), (2, false), (1, true)]);
+    assert_eq!(handle.read(None).unwrap(), "42\n");
+  }
+
+  #[test]
+  fn full_buffer_carries_eom() {
+    // the message fills the buffer exactly, and is written in pieces
+    let mut handle = handle(5);
+    let mut writer = handle.message_writer();
+    writer.write_all(b"ME").unwrap();
+    writer.write_all(b"AS?").unwrap();
+    writer.write_all(b"").unwrap();
+    writer.end_message().unwrap();
+    drop(writer);
+
+    // so the last transfer holds data as well as EOM, never just EOM
+    let transfers = handle.transport().message_transfers();
+    assert_eq!(transfers[1..], [(5, true)]);
+    assert_eq!(handle.read(None).unwrap(), "42\n");
+  }
+}
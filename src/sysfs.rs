@@ -0,0 +1,353 @@
+use crate::instrument::list_instruments_with;
+use crate::{Instrument, TMCResult};
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where Linux lists USB devices and interfaces
+const DEFAULT_SYSFS_ROOT: &str = "/sys/bus/usb/devices";
+
+/// A TMC interface found in sysfs, with the attributes the kernel keeps for
+/// its device.  None of these need the device to be opened.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SysfsInstrument {
+  /// The name of the interface's sysfs directory, such as `1-2.3:1.0`
+  pub name: String,
+
+  pub bus_number: u8,
+  pub address: u8,
+
+  /// The chain of hub port numbers leading to the device
+  pub port_numbers: Vec<u8>,
+
+  pub vendor_id: u16,
+  pub product_id: u16,
+  pub serial_number: Option<String>,
+  pub manufacturer: Option<String>,
+  pub product: Option<String>,
+
+  /// The configuration the interface belongs to, which is the device's
+  /// active one
+  pub configuration: u8,
+  pub interface_number: u8,
+  pub alternate_setting: u8,
+  pub interface_class: u8,
+  pub interface_subclass: u8,
+  pub interface_protocol: u8,
+  pub interface_description: Option<String>,
+}
+
+/// Finds instruments through Linux sysfs instead of asking the devices.
+///
+/// [list_instruments](crate::list_instruments) opens every TMC device to
+/// read its serial number, which is slow, needs permission to open the
+/// device, and can disturb a device another process is using.  The kernel
+/// already has all of that in sysfs, readable by anyone.  The root can be
+/// changed from `/sys/bus/usb/devices`, for example to point at a copy of the
+/// tree.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SysfsDiscovery {
+  root: PathBuf,
+}
+
+impl Default for SysfsDiscovery {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl SysfsDiscovery {
+  pub fn new() -> Self {
+    Self::with_root(DEFAULT_SYSFS_ROOT)
+  }
+
+  /// Look for devices in a directory laid out like `/sys/bus/usb/devices`
+  pub fn with_root<P: AsRef<Path>>(root: P) -> Self {
+    Self {
+      root: root.as_ref().to_owned(),
+    }
+  }
+
+  pub fn root(&self) -> &Path {
+    &self.root
+  }
+
+  /// List the TMC interfaces described in sysfs.  Only each device's active
+  /// configuration and each interface's current alternate setting appear
+  /// there.
+  pub fn list(&self) -> io::Result<Vec<SysfsInstrument>> {
+    let mut instruments = Vec::new();
+
+    for entry in fs::read_dir(&self.root)? {
+      let name = entry?.file_name().to_string_lossy().into_owned();
+
+      // interfaces are named `<device>:<config>.<interface>`
+      let (device_name, configuration) = match name.split_once(':') {
+        Some((device_name, rest)) => match rest.split_once('.').map(|(config, _)| config.parse()) {
+          Some(Ok(configuration)) => (device_name.to_owned(), configuration),
+          _ => continue,
+        },
+        None => continue,
+      };
+
+      // devices can come and go while we look, so skip any that we can't read
+      if let Ok(Some(instrument)) = self.read_interface(&name, &device_name, configuration) {
+        instruments.push(instrument);
+      }
+    }
+
+    instruments.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(instruments)
+  }
+
+  fn read_interface(
+    &self,
+    name: &str,
+    device_name: &str,
+    configuration: u8,
+  ) -> io::Result<Option<SysfsInstrument>> {
+    let interface = self.root.join(name);
+    let device = self.root.join(device_name);
+
+    let interface_class = read_hex(&interface, "bInterfaceClass")?;
+    let interface_subclass = read_hex(&interface, "bInterfaceSubClass")?;
+    if interface_class != 0xFE || interface_subclass != 3 {
+      return Ok(None);
+    }
+
+    let port_numbers = read_string(&device, "devpath")?
+      .unwrap_or_default()
+      .split('.')
+      .map(|port| port.parse().map_err(|_| invalid_data("devpath")))
+      .collect::<io::Result<Vec<u8>>>()?;
+
+    Ok(Some(SysfsInstrument {
+      name: name.to_owned(),
+
+      bus_number: read_decimal(&device, "busnum")?,
+      address: read_decimal(&device, "devnum")?,
+      port_numbers,
+
+      vendor_id: read_hex(&device, "idVendor")?,
+      product_id: read_hex(&device, "idProduct")?,
+      serial_number: read_string(&device, "serial")?,
+      manufacturer: read_string(&device, "manufacturer")?,
+      product: read_string(&device, "product")?,
+
+      configuration,
+      interface_number: read_hex(&interface, "bInterfaceNumber")?,
+      alternate_setting: read_decimal(&interface, "bAlternateSetting")?,
+      interface_class,
+      interface_subclass,
+      interface_protocol: read_hex(&interface, "bInterfaceProtocol")?,
+      interface_description: read_string(&interface, "interface")?,
+    }))
+  }
+
+  /// List detected USBTMC devices like [list_instruments](crate::list_instruments),
+  /// but taking their strings from sysfs instead of opening them.  Only
+  /// interfaces found in sysfs are listed.
+  pub fn list_instruments<Ctx: rusb::UsbContext>(
+    &self,
+    context: Ctx,
+  ) -> TMCResult<Vec<Instrument<Ctx>>> {
+    let found = self.list().map_err(|err| match err.kind() {
+      io::ErrorKind::NotFound => rusb::Error::NotFound,
+      io::ErrorKind::PermissionDenied => rusb::Error::Access,
+      _ => rusb::Error::Io,
+    })?;
+
+    let mut instruments = list_instruments_with(
+      context,
+      |device| {
+        found
+          .iter()
+          .any(|sysfs| sysfs.bus_number == device.bus_number() && sysfs.address == device.address())
+      },
+      false,
+    )?;
+
+    instruments.retain_mut(|instrument| {
+      let id = instrument.id();
+      let sysfs = found.iter().find(|sysfs| {
+        sysfs.bus_number == id.bus_number
+          && sysfs.address == id.address
+          && sysfs.configuration == instrument.config_desc.number()
+          && sysfs.interface_number == id.interface_number
+          && sysfs.alternate_setting == id.alternate_setting
+      });
+
+      match sysfs {
+        None => false,
+        Some(sysfs) => {
+          instrument.preload_strings(
+            sysfs.serial_number.clone(),
+            sysfs.manufacturer.clone(),
+            sysfs.product.clone(),
+            sysfs.interface_description.clone(),
+          );
+          true
+        }
+      }
+    });
+
+    Ok(instruments)
+  }
+}
+
+fn invalid_data(attribute: &str) -> io::Error {
+  io::Error::new(
+    io::ErrorKind::InvalidData,
+    format!("malformed sysfs attribute {}", attribute),
+  )
+}
+
+/// Read a sysfs attribute, which is missing if the device doesn't have it
+fn read_string(dir: &Path, attribute: &str) -> io::Result<Option<String>> {
+  match fs::read_to_string(dir.join(attribute)) {
+    Ok(value) => Ok(Some(value.trim_end_matches('\n').to_owned())),
+    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+    Err(err) => Err(err),
+  }
+}
+
+fn read_number<N: TryFrom<u32>>(dir: &Path, attribute: &str, radix: u32) -> io::Result<N> {
+  let value = read_string(dir, attribute)?.ok_or_else(|| invalid_data(attribute))?;
+
+  u32::from_str_radix(value.trim(), radix)
+    .ok()
+    .and_then(|value| N::try_from(value).ok())
+    .ok_or_else(|| invalid_data(attribute))
+}
+
+fn read_hex<N: TryFrom<u32>>(dir: &Path, attribute: &str) -> io::Result<N> {
+  read_number(dir, attribute, 16)
+}
+
+fn read_decimal<N: TryFrom<u32>>(dir: &Path, attribute: &str) -> io::Result<N> {
+  read_number(dir, attribute, 10)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A directory under the system temp dir, deleted when dropped
+  struct TempTree(PathBuf);
+
+  impl TempTree {
+    fn new(name: &str) -> Self {
+      let path = std::env::temp_dir().join(format!("tmc-{}-{}", name, std::process::id()));
+      let _ = fs::remove_dir_all(&path);
+      fs::create_dir_all(&path).unwrap();
+      TempTree(path)
+    }
+
+    fn write(&self, dir: &str, attributes: &[(&str, &str)]) {
+      let dir = self.0.join(dir);
+      fs::create_dir_all(&dir).unwrap();
+      for (attribute, value) in attributes {
+        fs::write(dir.join(attribute), format!("{}\n", value)).unwrap();
+      }
+    }
+  }
+
+  impl Drop for TempTree {
+    fn drop(&mut self) {
+      let _ = fs::remove_dir_all(&self.0);
+    }
+  }
+
+  fn device_attributes<'a>(devpath: &'a str, devnum: &'a str) -> Vec<(&'a str, &'a str)> {
+    vec![
+      ("busnum", "1"),
+      ("devnum", devnum),
+      ("devpath", devpath),
+      ("idVendor", "0957"),
+      ("idProduct", "2b18"),
+      ("manufacturer", "Agilent Technologies"),
+      ("product", "U2001A"),
+    ]
+  }
+
+  fn interface_attributes<'a>(
+    class: &'a str,
+    subclass: &'a str,
+    number: &'a str,
+  ) -> Vec<(&'a str, &'a str)> {
+    vec![
+      ("bInterfaceClass", class),
+      ("bInterfaceSubClass", subclass),
+      ("bInterfaceProtocol", "01"),
+      ("bInterfaceNumber", number),
+      ("bAlternateSetting", " 0"),
+    ]
+  }
+
+  #[test]
+  fn lists_tmc_interfaces() {
+    let tree = TempTree::new("sysfs-list");
+
+    // a root hub, whose interface isn't TMC
+    tree.write("usb1", &[("busnum", "1"), ("devnum", "1")]);
+    tree.write("1-0:1.0", &interface_attributes("09", "00", "00"));
+
+    // a device with a TMC interface and a vendor-specific one
+    let mut device = device_attributes("2.3", "7");
+    device.push(("serial", "MY1234"));
+    tree.write("1-2.3", &device);
+    let mut tmc_interface = interface_attributes("fe", "03", "00");
+    tmc_interface.push(("interface", "USBTMC"));
+    tree.write("1-2.3:2.0", &tmc_interface);
+    tree.write("1-2.3:2.1", &interface_attributes("ff", "00", "01"));
+
+    // a device without a serial number
+    tree.write("1-4", &device_attributes("4", "9"));
+    tree.write("1-4:1.0", &interface_attributes("fe", "03", "00"));
+
+    let found = SysfsDiscovery::with_root(&tree.0).list().unwrap();
+
+    assert_eq!(
+      found,
+      vec![
+        SysfsInstrument {
+          name: "1-2.3:2.0".to_owned(),
+          bus_number: 1,
+          address: 7,
+          port_numbers: vec![2, 3],
+          vendor_id: 0x0957,
+          product_id: 0x2b18,
+          serial_number: Some("MY1234".to_owned()),
+          manufacturer: Some("Agilent Technologies".to_owned()),
+          product: Some("U2001A".to_owned()),
+          configuration: 2,
+          interface_number: 0,
+          alternate_setting: 0,
+          interface_class: 0xFE,
+          interface_subclass: 3,
+          interface_protocol: 1,
+          interface_description: Some("USBTMC".to_owned()),
+        },
+        SysfsInstrument {
+          name: "1-4:1.0".to_owned(),
+          bus_number: 1,
+          address: 9,
+          port_numbers: vec![4],
+          vendor_id: 0x0957,
+          product_id: 0x2b18,
+          serial_number: None,
+          manufacturer: Some("Agilent Technologies".to_owned()),
+          product: Some("U2001A".to_owned()),
+          configuration: 1,
+          interface_number: 0,
+          alternate_setting: 0,
+          interface_class: 0xFE,
+          interface_subclass: 3,
+          interface_protocol: 1,
+          interface_description: None,
+        },
+      ]
+    );
+  }
+}
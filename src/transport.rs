@@ -0,0 +1,692 @@
+use crate::{Instrument, OpenOptions, TMCResult};
+use core::time::Duration;
+use rusb::DeviceHandle;
+use rusb::UsbContext;
+
+/// The USB operations needed to speak USBTMC to an instrument.
+///
+/// [InstrumentHandle](crate::InstrumentHandle) implements the class protocol
+/// on top of this trait, so it can run over any USB backend (or an in-memory
+/// simulation of a device).  [UsbTransport] is the default implementation,
+/// using rusb.
+///
+/// Errors are reported as [rusb::Error] so that the protocol logic can tell
+/// timeouts and stalls apart from other failures; backends should map their
+/// own errors to the closest equivalent.
+pub trait Transport {
+  /// Perform a control transfer from the device.  Same semantics as
+  /// [rusb::DeviceHandle::read_control].
+  fn read_control(
+    &self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    buf: &mut [u8],
+    timeout: Duration,
+  ) -> rusb::Result<usize>;
+
+  /// Write to a bulk-out endpoint, returning the number of bytes written.
+  fn write_bulk(&self, endpoint: u8, buf: &[u8], timeout: Duration) -> rusb::Result<usize>;
+
+  /// Read from a bulk-in endpoint, returning the number of bytes read.
+  fn read_bulk(&self, endpoint: u8, buf: &mut [u8], timeout: Duration) -> rusb::Result<usize>;
+
+  /// Read from an interrupt-in endpoint, returning the number of bytes read.
+  fn read_interrupt(&self, endpoint: u8, buf: &mut [u8], timeout: Duration) -> rusb::Result<usize>;
+
+  /// Clear the halt/stall condition on an endpoint.
+  fn clear_halt(&mut self, endpoint: u8) -> rusb::Result<()>;
+
+  /// The device's vendor and product ID, if known, by which its
+  /// [Quirks](crate::Quirks) are looked up.
+  fn device_ids(&self) -> Option<(u16, u16)> {
+    None
+  }
+}
+
+/// [Transport] for an [Instrument] attached through libusb.
+///
+/// Opening the transport claims the instrument's TMC interface and selects
+/// its alternate setting, detaching kernel drivers and switching
+/// configuration if needed.  All of that is
+/// undone when the transport is dropped.
+pub struct UsbTransport<Ctx: UsbContext> {
+  usb: DeviceHandle<Ctx>,
+
+  pub instrument: Instrument<Ctx>,
+
+  // When connecting, we may need to reconfigure some stuff.  Remember the
+  // previous state here and restore it on drop().
+  restore_config: Option<u8>,
+  reattach_kernel_driver: Vec<u8>,
+}
+
+impl<Ctx: UsbContext> Drop for UsbTransport<Ctx> {
+  fn drop(&mut self) {
+    // TODO: is there something more useful we can do if these fail?
+    let endpoints = &self.instrument.endpoints;
+
+    let _ = self.usb.release_interface(endpoints.interface_number);
+
+    if let Some(old_config) = self.restore_config {
+      let _ = self.usb.set_active_configuration(old_config);
+    }
+
+    for &interface in self.reattach_kernel_driver.iter() {
+      let _ = self.usb.attach_kernel_driver(interface);
+    }
+  }
+}
+
+impl<Ctx: UsbContext> UsbTransport<Ctx> {
+  pub fn open(instrument: Instrument<Ctx>) -> TMCResult<Self> {
+    Self::open_with(instrument, &OpenOptions::new())
+  }
+
+  /// Claim the instrument's TMC interface, following `options` about kernel
+  /// drivers and the alternate setting to select
+  pub fn open_with(instrument: Instrument<Ctx>, options: &OpenOptions) -> TMCResult<Self> {
+    let usb = instrument.device.open()?;
+
+    let mut transport = Self {
+      instrument,
+      usb,
+
+      restore_config: None,
+      reattach_kernel_driver: Vec::new(),
+    };
+    let usb = &mut transport.usb;
+    let endpoints = &transport.instrument.endpoints;
+
+    let old_config = usb.active_configuration()?;
+    let new_config = transport.instrument.config_desc.number();
+
+    // Only the TMC interface needs to be free, unless the configuration has to
+    // change, which means taking every interface of the old one from its driver.
+    let mut interfaces = Vec::new();
+    if old_config == new_config {
+      interfaces.push(endpoints.interface_number);
+    } else if old_config != 0 {
+      match transport.instrument.device.config_descriptor(old_config) {
+        Err(rusb::Error::NotFound) => {}
+        Err(rusb_error) => return Err(rusb_error.into()),
+        Ok(old_config_desc) => interfaces.extend(0..old_config_desc.num_interfaces()),
+      };
+    }
+
+    for interface in interfaces {
+      if usb.kernel_driver_active(interface)? {
+        if !options.get_detach_kernel_driver() {
+          return Err(rusb::Error::Busy.into());
+        }
+
+        transport.reattach_kernel_driver.push(interface);
+        usb.detach_kernel_driver(interface)?;
+      }
+    }
+
+    if old_config != new_config {
+      transport.restore_config = Some(old_config);
+      usb.set_active_configuration(new_config)?;
+    }
+
+    usb.claim_interface(endpoints.interface_number)?;
+
+    // Alternate setting 0 is selected when the interface is claimed, unless
+    // someone else changed it; only ask for it if we were told to.
+    if endpoints.alternate_setting != 0 || options.get_alternate_setting().is_some() {
+      usb.set_alternate_setting(endpoints.interface_number, endpoints.alternate_setting)?;
+    }
+
+    Ok(transport)
+  }
+
+  /// The underlying rusb device handle, for anything not covered by [Transport]
+  pub fn device_handle(&self) -> &DeviceHandle<Ctx> {
+    &self.usb
+  }
+}
+
+impl<Ctx: UsbContext> Transport for UsbTransport<Ctx> {
+  fn device_ids(&self) -> Option<(u16, u16)> {
+    let device_desc = &self.instrument.device_desc;
+    Some((device_desc.vendor_id(), device_desc.product_id()))
+  }
+
+  fn read_control(
+    &self,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    buf: &mut [u8],
+    timeout: Duration,
+  ) -> rusb::Result<usize> {
+    self
+      .usb
+      .read_control(request_type, request, value, index, buf, timeout)
+  }
+
+  fn write_bulk(&self, endpoint: u8, buf: &[u8], timeout: Duration) -> rusb::Result<usize> {
+    self.usb.write_bulk(endpoint, buf, timeout)
+  }
+
+  fn read_bulk(&self, endpoint: u8, buf: &mut [u8], timeout: Duration) -> rusb::Result<usize> {
+    self.usb.read_bulk(endpoint, buf, timeout)
+  }
+
+  fn read_interrupt(&self, endpoint: u8, buf: &mut [u8], timeout: Duration) -> rusb::Result<usize> {
+    self.usb.read_interrupt(endpoint, buf, timeout)
+  }
+
+  fn clear_halt(&mut self, endpoint: u8) -> rusb::Result<()> {
+    self.usb.clear_halt(endpoint)
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+  use super::*;
+  use crate::class::*;
+  use crate::{InstrumentHandle, Quirks, RecoveryPolicy, TMCError};
+  use byteorder::{ByteOrder, LittleEndian};
+  use std::cell::{Cell, RefCell};
+  use std::collections::{HashMap, VecDeque};
+  use std::convert::TryFrom;
+
+  /// A USB488 instrument simulated in memory, which answers `*IDN?` and
+  /// `MEAS?` and remembers the control requests it was sent
+  #[derive(Default)]
+  pub(crate) struct FakeTransport {
+    /// Bytes waiting to be read from bulk-in, one transfer per entry
+    bulk_in: RefCell<VecDeque<Vec<u8>>>,
+    /// The command message being received
+    message: RefCell<Vec<u8>>,
+    /// The size and EOM bit of every command transfer received
+    message_transfers: RefCell<Vec<(usize, bool)>>,
+    /// The reply to the last complete command
+    reply: RefCell<Vec<u8>>,
+    /// How many replies to an earlier session's requests are still to arrive,
+    /// just ahead of the next reply
+    late_stale_replies: Cell<usize>,
+    control_requests: RefCell<Vec<u8>>,
+    /// Responses to give to particular control requests, in order; the last
+    /// one is repeated for as long as the request keeps being made
+    control_responses: RefCell<HashMap<u8, VecDeque<Vec<u8>>>>,
+    /// Errors to fail the next bulk transfers with, in each direction
+    bulk_out_errors: RefCell<VecDeque<rusb::Error>>,
+    bulk_in_errors: RefCell<VecDeque<rusb::Error>>,
+    halts_cleared: RefCell<Vec<u8>>,
+    device_ids: Option<(u16, u16)>,
+  }
+
+  impl FakeTransport {
+    pub(crate) fn message_transfers(&self) -> Vec<(usize, bool)> {
+      self.message_transfers.borrow().clone()
+    }
+
+    fn script(&self, request: ControlRequest, responses: &[&[u8]]) {
+      self.control_responses.borrow_mut().insert(
+        request.into(),
+        responses.iter().map(|response| response.to_vec()).collect(),
+      );
+    }
+
+    fn control_response(&self, request: u8) -> Option<Vec<u8>> {
+      let mut control_responses = self.control_responses.borrow_mut();
+      let responses = control_responses.get_mut(&request)?;
+      if responses.len() > 1 {
+        responses.pop_front()
+      } else {
+        responses.front().cloned()
+      }
+    }
+
+    fn respond(&self, command: &[u8]) -> Vec<u8> {
+      match command {
+        b"*IDN?" => b"FAKE,MODEL,1234,1.0\n".to_vec(),
+        b"MEAS?" => b"42\n".to_vec(),
+        _ => Vec::new(),
+      }
+    }
+  }
+
+  impl Transport for FakeTransport {
+    fn read_control(
+      &self,
+      _request_type: u8,
+      request: u8,
+      _value: u16,
+      _index: u16,
+      buf: &mut [u8],
+      _timeout: Duration,
+    ) -> rusb::Result<usize> {
+      self.control_requests.borrow_mut().push(request);
+
+      if let Some(response) = self.control_response(request) {
+        let n = buf.len().min(response.len());
+        buf[..n].copy_from_slice(&response[..n]);
+        return Ok(n);
+      }
+
+      let mut response = [0u8; 0x18];
+      // STATUS_SUCCESS
+      response[0] = 0x01;
+      if request == u8::from(ControlRequest::GetCapabilities) {
+        LittleEndian::write_u16(&mut response[2..4], 0x0100);
+        LittleEndian::write_u16(&mut response[12..14], 0x0100);
+        // USB488.2 with SCPI and service requests
+        response[14] = 0x04;
+        response[15] = 0x0C;
+      }
+
+      let n = buf.len().min(response.len());
+      buf[..n].copy_from_slice(&response[..n]);
+      Ok(n)
+    }
+
+    fn write_bulk(&self, _endpoint: u8, buf: &[u8], _timeout: Duration) -> rusb::Result<usize> {
+      if let Some(error) = self.bulk_out_errors.borrow_mut().pop_front() {
+        return Err(error);
+      }
+
+      let b_tag = buf[1];
+      let transfer_size = LittleEndian::read_u32(&buf[4..8]) as usize;
+
+      match MsgIdOut::try_from(buf[0]) {
+        Ok(MsgIdOut::DevDepMsgOut) => {
+          let eom = buf[8] & 0x01 != 0;
+          self
+            .message_transfers
+            .borrow_mut()
+            .push((transfer_size, eom));
+
+          let mut message = self.message.borrow_mut();
+          message.extend_from_slice(&buf[HEADER_SIZE..HEADER_SIZE + transfer_size]);
+          if eom {
+            *self.reply.borrow_mut() = self.respond(&message);
+            message.clear();
+          }
+        }
+        Ok(MsgIdOut::RequestDevDepMsgIn) => {
+          let mut reply = self.reply.borrow_mut();
+          let n = reply.len().min(transfer_size);
+          let data: Vec<u8> = reply.drain(..n).collect();
+
+          for _ in 0..self.late_stale_replies.replace(0) {
+            let mut stale = Vec::new();
+            DevDepMsgInHeader::encode_message(b_tag % 255 + 1, b"OLD\n", true, &mut stale);
+            self.bulk_in.borrow_mut().push_back(stale);
+          }
+
+          let mut transfer = Vec::new();
+          DevDepMsgInHeader::encode_message(b_tag, &data, reply.is_empty(), &mut transfer);
+          self.bulk_in.borrow_mut().push_back(transfer);
+        }
+        _ => return Err(rusb::Error::Pipe),
+      }
+
+      Ok(buf.len())
+    }
+
+    fn read_bulk(&self, _endpoint: u8, buf: &mut [u8], _timeout: Duration) -> rusb::Result<usize> {
+      if let Some(error) = self.bulk_in_errors.borrow_mut().pop_front() {
+        return Err(error);
+      }
+
+      let mut bulk_in = self.bulk_in.borrow_mut();
+      let transfer = bulk_in.front_mut().ok_or(rusb::Error::Timeout)?;
+
+      let n = buf.len().min(transfer.len());
+      buf[..n].copy_from_slice(&transfer[..n]);
+      transfer.drain(..n);
+      if transfer.is_empty() {
+        bulk_in.pop_front();
+      }
+      Ok(n)
+    }
+
+    fn read_interrupt(
+      &self,
+      _endpoint: u8,
+      _buf: &mut [u8],
+      _timeout: Duration,
+    ) -> rusb::Result<usize> {
+      Err(rusb::Error::Timeout)
+    }
+
+    fn clear_halt(&mut self, endpoint: u8) -> rusb::Result<()> {
+      self.halts_cleared.borrow_mut().push(endpoint);
+      Ok(())
+    }
+
+    fn device_ids(&self) -> Option<(u16, u16)> {
+      self.device_ids
+    }
+  }
+
+  #[test]
+  fn connect_and_ask() {
+    let transport = FakeTransport::default();
+    // left over from an interrupted session
+    transport
+      .bulk_in
+      .borrow_mut()
+      .push_back(b"stale reply".to_vec());
+
+    let mut handle = InstrumentHandle::connect(transport, TMCInterface::for_tests()).unwrap();
+
+    assert!(handle.usbtmc_capabilities.is_valid());
+    assert!(handle.usb488_capabilities.as_ref().unwrap().scpi);
+    assert_eq!(handle.scpi_id.as_deref(), Some("FAKE,MODEL,1234,1.0"));
+    assert!(handle
+      .transport()
+      .control_requests
+      .borrow()
+      .contains(&ControlRequest::InitiateClear.into()));
+
+    assert_eq!(handle.ask("MEAS?").unwrap(), "42\n");
+    assert!(handle.transport().bulk_in.borrow().is_empty());
+  }
+
+  #[test]
+  fn connect_looks_up_quirks() {
+    let quirks = Quirks {
+      max_transfer_size: Some(16),
+      ..Quirks::default()
+    };
+    // an ID no real instrument uses, so no other test sees these quirks
+    Quirks::register(0xFFFF, 0x0001, quirks);
+
+    let transport = FakeTransport {
+      device_ids: Some((0xFFFF, 0x0001)),
+      ..FakeTransport::default()
+    };
+    let handle = InstrumentHandle::connect(transport, TMCInterface::for_tests()).unwrap();
+
+    assert_eq!(handle.get_quirks(), quirks);
+    assert_eq!(handle.get_max_transfer_size(), 16);
+  }
+
+  #[test]
+  fn stale_reply_after_flush() {
+    // too late to be flushed, so it turns up while reading the reply to `*IDN?`
+    let transport = FakeTransport::default();
+    transport.late_stale_replies.set(1);
+
+    let mut handle = InstrumentHandle::connect(transport, TMCInterface::for_tests()).unwrap();
+    assert_eq!(handle.scpi_id.as_deref(), Some("FAKE,MODEL,1234,1.0"));
+
+    // and nothing is left queued to upset the next command
+    assert!(handle.transport().bulk_in.borrow().is_empty());
+    assert_eq!(handle.ask("MEAS?").unwrap(), "42\n");
+  }
+
+  const STATUS_SUCCESS: u8 = 0x01;
+  const STATUS_PENDING: u8 = 0x02;
+  const STATUS_FAILED: u8 = 0x80;
+
+  /// Connect to `transport`, then forget the control requests made so far
+  fn connect(transport: FakeTransport) -> InstrumentHandle<FakeTransport> {
+    let handle = InstrumentHandle::connect(transport, TMCInterface::for_tests()).unwrap();
+    handle.transport().control_requests.borrow_mut().clear();
+    handle.transport().halts_cleared.borrow_mut().clear();
+    handle
+  }
+
+  fn requests(requests: &[ControlRequest]) -> Vec<u8> {
+    requests.iter().map(|&request| request.into()).collect()
+  }
+
+  #[test]
+  fn abort_bulk_out_with_nothing_in_progress() {
+    let transport = FakeTransport::default();
+    transport.script(ControlRequest::InitiateAbortBulkOut, &[&[STATUS_FAILED, 0]]);
+    let mut handle = connect(transport);
+
+    handle.abort_bulk_out().unwrap();
+
+    // FAILED means there is nothing to abort, so the status isn't polled
+    assert_eq!(
+      *handle.transport().control_requests.borrow(),
+      requests(&[ControlRequest::InitiateAbortBulkOut])
+    );
+    assert!(handle.transport().halts_cleared.borrow().is_empty());
+  }
+
+  #[test]
+  fn abort_bulk_out_polls_while_pending() {
+    let transport = FakeTransport::default();
+    transport.script(
+      ControlRequest::InitiateAbortBulkOut,
+      &[&[STATUS_SUCCESS, 0]],
+    );
+    transport.script(
+      ControlRequest::CheckAbortBulkOutStatus,
+      &[
+        &[STATUS_PENDING, 0, 0, 0, 0, 0, 0, 0],
+        &[STATUS_SUCCESS, 0, 0, 0, 0, 0, 0, 0],
+      ],
+    );
+    let mut handle = connect(transport);
+
+    handle.abort_bulk_out().unwrap();
+
+    assert_eq!(
+      *handle.transport().control_requests.borrow(),
+      requests(&[
+        ControlRequest::InitiateAbortBulkOut,
+        ControlRequest::CheckAbortBulkOutStatus,
+        ControlRequest::CheckAbortBulkOutStatus,
+      ])
+    );
+    assert_eq!(*handle.transport().halts_cleared.borrow(), [0x01]);
+  }
+
+  #[test]
+  fn abort_bulk_out_gives_up_at_the_deadline() {
+    let transport = FakeTransport::default();
+    transport.script(
+      ControlRequest::InitiateAbortBulkOut,
+      &[&[STATUS_SUCCESS, 0]],
+    );
+    transport.script(
+      ControlRequest::CheckAbortBulkOutStatus,
+      &[&[STATUS_PENDING, 0, 0, 0, 0, 0, 0, 0]],
+    );
+    let mut handle = connect(transport);
+    handle.set_timeout(Duration::from_millis(150));
+
+    assert!(matches!(
+      handle.abort_bulk_out(),
+      Err(TMCError::Rusb(rusb::Error::Timeout))
+    ));
+    assert!(handle.transport().halts_cleared.borrow().is_empty());
+  }
+
+  #[test]
+  fn abort_bulk_in_with_nothing_in_progress() {
+    let transport = FakeTransport::default();
+    transport.script(ControlRequest::InitiateAbortBulkIn, &[&[STATUS_FAILED, 0]]);
+    let mut handle = connect(transport);
+    handle
+      .transport()
+      .bulk_in
+      .borrow_mut()
+      .push_back(b"not part of a transfer".to_vec());
+
+    handle.abort_bulk_in().unwrap();
+
+    // FAILED means there is nothing to abort, so nothing is drained or polled
+    assert_eq!(
+      *handle.transport().control_requests.borrow(),
+      requests(&[ControlRequest::InitiateAbortBulkIn])
+    );
+    assert_eq!(handle.transport().bulk_in.borrow().len(), 1);
+  }
+
+  #[test]
+  fn abort_bulk_in_drains_while_more_data() {
+    let transport = FakeTransport::default();
+    transport.script(ControlRequest::InitiateAbortBulkIn, &[&[STATUS_SUCCESS, 0]]);
+    transport.script(
+      ControlRequest::CheckAbortBulkInStatus,
+      &[
+        // bmAbortBulkIn.D0 set: the device has more of the transfer queued
+        &[STATUS_PENDING, 0x01, 0, 0, 0, 0, 0, 0],
+        &[STATUS_SUCCESS, 0, 0, 0, 0, 0, 0, 0],
+      ],
+    );
+    let mut handle = connect(transport);
+    {
+      let mut bulk_in = handle.transport().bulk_in.borrow_mut();
+      bulk_in.push_back(b"aborted transfer".to_vec());
+      bulk_in.push_back(b"rest of the aborted transfer".to_vec());
+    }
+
+    handle.abort_bulk_in().unwrap();
+
+    assert_eq!(
+      *handle.transport().control_requests.borrow(),
+      requests(&[
+        ControlRequest::InitiateAbortBulkIn,
+        ControlRequest::CheckAbortBulkInStatus,
+        ControlRequest::CheckAbortBulkInStatus,
+      ])
+    );
+    assert!(handle.transport().bulk_in.borrow().is_empty());
+  }
+
+  #[test]
+  fn abort_bulk_in_gives_up_at_the_deadline() {
+    let transport = FakeTransport::default();
+    transport.script(ControlRequest::InitiateAbortBulkIn, &[&[STATUS_SUCCESS, 0]]);
+    transport.script(
+      ControlRequest::CheckAbortBulkInStatus,
+      &[&[STATUS_PENDING, 0, 0, 0, 0, 0, 0, 0]],
+    );
+    let mut handle = connect(transport);
+    handle.set_timeout(Duration::from_millis(150));
+
+    assert!(matches!(
+      handle.abort_bulk_in(),
+      Err(TMCError::Rusb(rusb::Error::Timeout))
+    ));
+  }
+
+  #[test]
+  fn bulk_in_timeout_is_left_to_the_application() {
+    let transport = FakeTransport::default();
+    let mut handle = connect(transport);
+    handle
+      .transport()
+      .bulk_in_errors
+      .borrow_mut()
+      .push_back(rusb::Error::Timeout);
+
+    assert!(matches!(
+      handle.ask("MEAS?"),
+      Err(TMCError::Rusb(rusb::Error::Timeout))
+    ));
+    assert!(handle.transport().control_requests.borrow().is_empty());
+    assert!(handle.transport().halts_cleared.borrow().is_empty());
+  }
+
+  #[test]
+  fn bulk_in_timeout_is_recovered() {
+    let transport = FakeTransport::default();
+    transport.script(ControlRequest::InitiateAbortBulkIn, &[&[STATUS_SUCCESS, 0]]);
+    transport.script(
+      ControlRequest::CheckAbortBulkInStatus,
+      &[&[STATUS_SUCCESS, 0, 0, 0, 0, 0, 0, 0]],
+    );
+    let mut handle = connect(transport);
+    handle
+      .transport()
+      .bulk_in_errors
+      .borrow_mut()
+      .push_back(rusb::Error::Timeout);
+    handle.set_recovery_policy(RecoveryPolicy::Abort);
+
+    assert!(matches!(
+      handle.ask("MEAS?"),
+      Err(TMCError::Recovered(rusb::Error::Timeout))
+    ));
+    assert_eq!(*handle.transport().halts_cleared.borrow(), [0x82]);
+    assert_eq!(
+      *handle.transport().control_requests.borrow(),
+      requests(&[
+        ControlRequest::InitiateAbortBulkIn,
+        ControlRequest::CheckAbortBulkInStatus,
+      ])
+    );
+
+    // the lost reply was drained, so the next one isn't mixed up with it
+    assert!(handle.transport().bulk_in.borrow().is_empty());
+    assert_eq!(handle.ask("MEAS?").unwrap(), "42\n");
+  }
+
+  #[test]
+  fn bulk_out_stall_is_recovered() {
+    let transport = FakeTransport::default();
+    transport.script(
+      ControlRequest::InitiateAbortBulkOut,
+      &[&[STATUS_SUCCESS, 0]],
+    );
+    transport.script(
+      ControlRequest::CheckAbortBulkOutStatus,
+      &[&[STATUS_SUCCESS, 0, 0, 0, 0, 0, 0, 0]],
+    );
+    let mut handle = connect(transport);
+    handle
+      .transport()
+      .bulk_out_errors
+      .borrow_mut()
+      .push_back(rusb::Error::Pipe);
+    handle.set_recovery_policy(RecoveryPolicy::Abort);
+
+    assert!(matches!(
+      handle.write("MEAS?"),
+      Err(TMCError::Recovered(rusb::Error::Pipe))
+    ));
+    // once before the abort, and again once it has completed
+    assert_eq!(*handle.transport().halts_cleared.borrow(), [0x01, 0x01]);
+    assert_eq!(
+      *handle.transport().control_requests.borrow(),
+      requests(&[
+        ControlRequest::InitiateAbortBulkOut,
+        ControlRequest::CheckAbortBulkOutStatus,
+      ])
+    );
+
+    assert_eq!(handle.ask("MEAS?").unwrap(), "42\n");
+  }
+
+  #[test]
+  fn failed_recovery_reports_the_original_error() {
+    let transport = FakeTransport::default();
+    transport.script(
+      ControlRequest::InitiateAbortBulkOut,
+      &[&[STATUS_SUCCESS, 0]],
+    );
+    transport.script(
+      ControlRequest::CheckAbortBulkOutStatus,
+      &[&[STATUS_PENDING, 0, 0, 0, 0, 0, 0, 0]],
+    );
+    let mut handle = connect(transport);
+    handle
+      .transport()
+      .bulk_out_errors
+      .borrow_mut()
+      .push_back(rusb::Error::Pipe);
+    handle.set_recovery_policy(RecoveryPolicy::Abort);
+    handle.set_timeout(Duration::from_millis(150));
+
+    // the abort times out, but it's the stall that gets reported
+    assert!(matches!(
+      handle.write("MEAS?"),
+      Err(TMCError::Rusb(rusb::Error::Pipe))
+    ));
+  }
+}
@@ -0,0 +1,256 @@
+use crate::instrument::list_instruments_with;
+use crate::{Instrument, InstrumentId, TMCError, TMCResult};
+use core::time::Duration;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+/// How often the watcher thread checks whether it should stop
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often to rescan the bus when libusb can't report hotplug events
+const DEFAULT_RESCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A change in the set of connected instruments, reported by an
+/// [InstrumentWatcher]
+#[derive(Debug)]
+pub enum InstrumentEvent<Ctx: rusb::UsbContext> {
+  /// A TMC interface was found.  A device with several TMC interfaces
+  /// arrives once for each of them.
+  Arrived(Instrument<Ctx>),
+
+  /// A TMC interface that had arrived went away
+  Left(InstrumentId),
+}
+
+/// Watches the USB bus for instruments being plugged in and unplugged.
+///
+/// Instruments already connected when the watcher starts are reported as
+/// arriving first.  Where libusb supports hotplug notification it's used;
+/// otherwise the bus is rescanned periodically.  The events are collected on
+/// a background thread, which stops when the watcher is dropped.  Devices
+/// aren't opened while scanning, so strings such as the serial number are
+/// only read when asked for.
+pub struct InstrumentWatcher<Ctx: rusb::UsbContext> {
+  events: mpsc::Receiver<InstrumentEvent<Ctx>>,
+  stop: Arc<AtomicBool>,
+  thread: Option<thread::JoinHandle<()>>,
+}
+
+impl<Ctx: rusb::UsbContext> Drop for InstrumentWatcher<Ctx> {
+  fn drop(&mut self) {
+    self.stop.store(true, Ordering::Relaxed);
+
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
+
+impl<Ctx: rusb::UsbContext + 'static> InstrumentWatcher<Ctx> {
+  /// Start watching, using hotplug notification if libusb supports it
+  pub fn new(context: Ctx) -> TMCResult<Self> {
+    if rusb::has_hotplug() {
+      Self::hotplug(context)
+    } else {
+      Self::rescanning(context, DEFAULT_RESCAN_INTERVAL)
+    }
+  }
+
+  /// Start watching by rescanning the bus at the given interval, whether or
+  /// not hotplug notification is available
+  pub fn rescanning(context: Ctx, interval: Duration) -> TMCResult<Self> {
+    Self::spawn(move |stop, events| {
+      let mut known = Known::default();
+
+      while !stop.load(Ordering::Relaxed) {
+        if let Ok(instruments) = list_instruments_with(context.clone(), |_| true, false) {
+          known.rescanned(instruments, &events);
+        }
+
+        let mut waited = Duration::from_secs(0);
+        while waited < interval && !stop.load(Ordering::Relaxed) {
+          let nap = STOP_CHECK_INTERVAL.min(interval - waited);
+          thread::sleep(nap);
+          waited += nap;
+        }
+      }
+    })
+  }
+
+  fn hotplug(context: Ctx) -> TMCResult<Self> {
+    let (changes_tx, changes) = mpsc::channel();
+    let registration = context.register_callback(
+      None,
+      None,
+      None,
+      Box::new(HotplugChanges {
+        changes: changes_tx,
+      }),
+    )?;
+
+    Self::spawn(move |stop, events| {
+      // dropped at the end of this thread, which unregisters the callback
+      let _registration = registration;
+      let mut known = Known::default();
+
+      if let Ok(instruments) = list_instruments_with(context.clone(), |_| true, false) {
+        known.rescanned(instruments, &events);
+      }
+
+      while !stop.load(Ordering::Relaxed) {
+        let _ = context.handle_events(Some(STOP_CHECK_INTERVAL));
+
+        // libusb mustn't be used from within the hotplug callback, so the
+        // devices are looked at here instead.
+        for change in changes.try_iter() {
+          match change {
+            Change::Arrived {
+              bus_number,
+              address,
+            } => {
+              let instruments = list_instruments_with(
+                context.clone(),
+                |device| device.bus_number() == bus_number && device.address() == address,
+                false,
+              );
+
+              for instrument in instruments.unwrap_or_default() {
+                known.arrived(instrument, &events);
+              }
+            }
+            Change::Left {
+              bus_number,
+              address,
+            } => {
+              known.left(
+                |id| id.bus_number == bus_number && id.address == address,
+                &events,
+              );
+            }
+          }
+        }
+      }
+    })
+  }
+
+  fn spawn<F>(watch: F) -> TMCResult<Self>
+  where
+    F: FnOnce(Arc<AtomicBool>, mpsc::Sender<InstrumentEvent<Ctx>>) + Send + 'static,
+  {
+    let (events_tx, events) = mpsc::channel();
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let thread_stop = stop.clone();
+    let thread = thread::Builder::new()
+      .name("tmc-watcher".to_owned())
+      .spawn(move || watch(thread_stop, events_tx))
+      .map_err(|_| TMCError::WorkerStopped)?;
+
+    Ok(Self {
+      events,
+      stop,
+      thread: Some(thread),
+    })
+  }
+}
+
+impl<Ctx: rusb::UsbContext> InstrumentWatcher<Ctx> {
+  /// Wait for the next event
+  pub fn recv(&self) -> TMCResult<InstrumentEvent<Ctx>> {
+    self.events.recv().map_err(|_| TMCError::WorkerStopped)
+  }
+
+  /// Wait up to `timeout` for the next event
+  pub fn recv_timeout(&self, timeout: Duration) -> TMCResult<Option<InstrumentEvent<Ctx>>> {
+    match self.events.recv_timeout(timeout) {
+      Ok(event) => Ok(Some(event)),
+      Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+      Err(mpsc::RecvTimeoutError::Disconnected) => Err(TMCError::WorkerStopped),
+    }
+  }
+
+  /// Get the next event if there is one, without waiting
+  pub fn try_recv(&self) -> TMCResult<Option<InstrumentEvent<Ctx>>> {
+    match self.events.try_recv() {
+      Ok(event) => Ok(Some(event)),
+      Err(mpsc::TryRecvError::Empty) => Ok(None),
+      Err(mpsc::TryRecvError::Disconnected) => Err(TMCError::WorkerStopped),
+    }
+  }
+}
+
+/// A device coming or going, as reported by a hotplug callback
+enum Change {
+  Arrived { bus_number: u8, address: u8 },
+  Left { bus_number: u8, address: u8 },
+}
+
+struct HotplugChanges {
+  changes: mpsc::Sender<Change>,
+}
+
+impl<Ctx: rusb::UsbContext> rusb::Hotplug<Ctx> for HotplugChanges {
+  fn device_arrived(&mut self, device: rusb::Device<Ctx>) {
+    let _ = self.changes.send(Change::Arrived {
+      bus_number: device.bus_number(),
+      address: device.address(),
+    });
+  }
+
+  fn device_left(&mut self, device: rusb::Device<Ctx>) {
+    let _ = self.changes.send(Change::Left {
+      bus_number: device.bus_number(),
+      address: device.address(),
+    });
+  }
+}
+
+/// The instruments the watcher has reported as present
+#[derive(Default)]
+struct Known {
+  ids: HashSet<InstrumentId>,
+}
+
+impl Known {
+  fn arrived<Ctx: rusb::UsbContext>(
+    &mut self,
+    instrument: Instrument<Ctx>,
+    events: &mpsc::Sender<InstrumentEvent<Ctx>>,
+  ) {
+    if self.ids.insert(instrument.id()) {
+      let _ = events.send(InstrumentEvent::Arrived(instrument));
+    }
+  }
+
+  fn left<Ctx: rusb::UsbContext>(
+    &mut self,
+    gone: impl Fn(&InstrumentId) -> bool,
+    events: &mpsc::Sender<InstrumentEvent<Ctx>>,
+  ) {
+    let gone_ids: Vec<InstrumentId> = self.ids.iter().copied().filter(|id| gone(id)).collect();
+
+    for id in gone_ids {
+      self.ids.remove(&id);
+      let _ = events.send(InstrumentEvent::Left(id));
+    }
+  }
+
+  /// Report the differences between a fresh scan and what was known before
+  fn rescanned<Ctx: rusb::UsbContext>(
+    &mut self,
+    instruments: Vec<Instrument<Ctx>>,
+    events: &mpsc::Sender<InstrumentEvent<Ctx>>,
+  ) {
+    let present: HashSet<InstrumentId> = instruments
+      .iter()
+      .map(|instrument| instrument.id())
+      .collect();
+    self.left(|id| !present.contains(id), events);
+
+    for instrument in instruments {
+      self.arrived(instrument, events);
+    }
+  }
+}